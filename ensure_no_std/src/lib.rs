@@ -0,0 +1,19 @@
+// A no_std crate with only alloc, it compiles as long as mcq does not need std.
+
+#![no_std]
+
+extern crate alloc;
+extern crate mcq;
+
+use alloc::vec::Vec;
+
+pub fn palette(pixels: &[u32], k_max: u32) -> Vec<(u8, u8, u8)> {
+    mcq::MMCQ::from_pixels_u32_rgba(pixels, k_max).palette_rgb()
+}
+
+pub fn quantize(pixels: &[u32], k_max: u32) -> Vec<u32> {
+    let m = mcq::MMCQ::from_pixels_u32_rgba(pixels, k_max);
+    let mut out = pixels.to_vec();
+    m.quantize_in_place(&mut out);
+    out
+}
@@ -35,8 +35,8 @@ fn process_image(file: &str) {
         MMCQ::from_pixels_u8_rgba(data.as_slice(), QUANT_SIZE)
     };
 
-    // A `Vec` of colors, descendantely sorted by usage frequency
-    let qc = mcq.get_quantized_colors();
+    // A `Vec` of colors with their pixel counts, descendantely sorted by usage frequency
+    let qc = mcq.palette_with_counts();
     // println!("Quantized {:?}", qc);
 
     // =============================================================================================
@@ -55,17 +55,21 @@ fn process_image(file: &str) {
         }
     }
 
-    let color_width = ix / QUANT_SIZE;
+    // Every color gets a swatch as wide as its share of the image pixels.
+    let total: usize = qc.iter().map(|&(_, cnt)| cnt).sum();
+    let mut x1 = 0;
+    let mut covered = 0;
 
-    for y in (iy + 1)..(iy + COLOR_HEIGHT) {
-        for x0 in 0..QUANT_SIZE {
-            let x1 = x0 * color_width;
-            let q = qc[x0 as usize];
+    for (i, &((red, grn, blu), cnt)) in qc.iter().enumerate() {
+        covered += cnt;
+        let x2 = if i + 1 == qc.len() { ix } else { (covered as u64 * ix as u64 / total as u64) as u32 };
 
-            for x2 in 0..color_width {
-                imgbuf.put_pixel(x1 + x2, y, image::Rgba([q.red, q.grn, q.blu, 0xff]));
+        for x in x1..x2 {
+            for y in (iy + 1)..(iy + COLOR_HEIGHT) {
+                imgbuf.put_pixel(x, y, image::Rgba([red, grn, blu, 0xff]));
             }
         }
+        x1 = x2;
     }
 
     let ref mut fout = File::create(format!("./target/{}.png",
@@ -0,0 +1,106 @@
+// Parser for Adobe Swatch Exchange (.ase) files, all numbers are big-endian:
+//
+// "ASEF", version (u16 major, u16 minor), number of blocks (u32)
+// every block: type (u16), data length (u32), data
+//   0x0001 color entry: name length (u16, UTF-16 units), name (UTF-16),
+//          color model (4 bytes, "RGB ", "CMYK", "LAB " or "Gray"),
+//          channel values (f32 each), color type (u16)
+//   0xC001 group start, 0xC002 group end
+//
+// Only the RGB color entries are read, groups and other color models are skipped.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+
+#[cfg(not(feature = "std"))]
+use float::FloatExt;
+
+const COLOR_ENTRY: u16 = 0x0001;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AseError {
+    MissingSignature,
+    Truncated { offset: usize },
+}
+
+impl fmt::Display for AseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AseError::MissingSignature => write!(f, "missing 'ASEF' signature"),
+            AseError::Truncated { offset } => write!(f, "unexpected end of data at offset {}", offset),
+        }
+    }
+}
+
+impl Error for AseError {}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], AseError> {
+        if self.bytes.len() - self.offset < len {
+            return Err(AseError::Truncated { offset: self.offset });
+        }
+        let data = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(data)
+    }
+
+    fn u16(&mut self) -> Result<u16, AseError> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, AseError> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn f32(&mut self) -> Result<f32, AseError> {
+        self.u32().map(f32::from_bits)
+    }
+}
+
+fn unit_to_channel(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Vec<(u8, u8, u8)>, AseError> {
+    if !bytes.starts_with(b"ASEF") {
+        return Err(AseError::MissingSignature);
+    }
+    let mut header = Reader { bytes, offset: 4 };
+    header.take(4)?; // version
+    let blocks = header.u32()?;
+
+    let mut colors = Vec::new();
+    let mut offset = header.offset;
+    for _ in 0..blocks {
+        let mut block = Reader { bytes, offset };
+        let block_type = block.u16()?;
+        let len = block.u32()? as usize;
+        block.take(len)?;
+        offset = block.offset;
+
+        if block_type != COLOR_ENTRY {
+            continue;
+        }
+        // the entry is read within the bounds of its block, offsets stay absolute for the errors
+        let mut entry = Reader { bytes: &bytes[..offset], offset: offset - len };
+        let name_len = entry.u16()? as usize;
+        entry.take(name_len * 2)?;
+        if entry.take(4)? != b"RGB " {
+            continue;
+        }
+        let r = entry.f32()?;
+        let g = entry.f32()?;
+        let b = entry.f32()?;
+        colors.push((unit_to_channel(r), unit_to_channel(g), unit_to_channel(b)));
+    }
+    Ok(colors)
+}
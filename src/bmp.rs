@@ -0,0 +1,64 @@
+// Writer for uncompressed 8-bit indexed BMP images
+// (BITMAPFILEHEADER + BITMAPINFOHEADER, palette, bottom-up rows padded to 4 bytes).
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use MmcqError;
+
+const FILE_HEADER_SIZE: u32 = 14;
+const INFO_HEADER_SIZE: u32 = 40;
+const PIXELS_PER_METER: u32 = 2835; // 72 DPI
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn encode_indexed(indices: &[u8], palette: &[(u8, u8, u8)], width: u32, height: u32) -> Result<Vec<u8>, MmcqError> {
+    assert!(palette.len() <= 256, "palette does not fit into an 8-bit BMP");
+
+    // the file size is stored in 32 bits, padded rows included
+    let palette_size = 4 * palette.len() as u32;
+    let data_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE + palette_size;
+    let row_size = width.checked_add(3).map(|w| w & !3);
+    let image_size = row_size.and_then(|row_size| row_size.checked_mul(height)).filter(|&size| size.checked_add(data_offset).is_some());
+    let (row_size, image_size) = match (row_size, image_size) {
+        (Some(row_size), Some(image_size)) => (row_size, image_size),
+        _ => return Err(MmcqError::TooLargeForBmp { width, height }),
+    };
+    assert_eq!(indices.len(), width as usize * height as usize);
+
+    let mut out = Vec::with_capacity((data_offset + image_size) as usize);
+    out.extend_from_slice(b"BM");
+    push_u32(&mut out, data_offset + image_size);
+    push_u32(&mut out, 0); // reserved
+    push_u32(&mut out, data_offset);
+
+    push_u32(&mut out, INFO_HEADER_SIZE);
+    push_u32(&mut out, width);
+    push_u32(&mut out, height); // positive height means bottom-up rows
+    push_u16(&mut out, 1); // planes
+    push_u16(&mut out, 8); // bits per pixel
+    push_u32(&mut out, 0); // BI_RGB, no compression
+    push_u32(&mut out, image_size);
+    push_u32(&mut out, PIXELS_PER_METER);
+    push_u32(&mut out, PIXELS_PER_METER);
+    push_u32(&mut out, palette.len() as u32);
+    push_u32(&mut out, 0); // all colors are important
+
+    for &(red, grn, blu) in palette {
+        out.extend_from_slice(&[blu, grn, red, 0]);
+    }
+
+    let padding = (row_size - width) as usize;
+    for row in indices.chunks(width.max(1) as usize).rev() {
+        out.extend_from_slice(row);
+        let len = out.len();
+        out.resize(len + padding, 0);
+    }
+    Ok(out)
+}
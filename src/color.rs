@@ -0,0 +1,112 @@
+// Color space conversions.
+//
+// OKLab by Björn Ottosson, see https://bottosson.github.io/posts/oklab/
+// L is in 0..1, a and b are roughly in -0.4..0.4 for the sRGB gamut.
+//
+// CIELAB is relative to the D65 white point, L is in 0..100.
+
+#[cfg(not(feature = "std"))]
+use float::FloatExt;
+
+fn srgb_to_linear(c: u8) -> f64 {
+    let c = c as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f64) -> u8 {
+    let c = if c <= 0.0031308 { 12.92 * c } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (c * 255.0 + 0.5).clamp(0.0, 255.0) as u8
+}
+
+pub fn srgb_to_oklab(red: u8, grn: u8, blu: u8) -> [f64; 3] {
+    let r = srgb_to_linear(red);
+    let g = srgb_to_linear(grn);
+    let b = srgb_to_linear(blu);
+
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s, 1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s, 0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s]
+}
+
+pub fn oklab_to_srgb(lab: [f64; 3]) -> (u8, u8, u8) {
+    let l = lab[0] + 0.3963377774 * lab[1] + 0.2158037573 * lab[2];
+    let m = lab[0] - 0.1055613458 * lab[1] - 0.0638541728 * lab[2];
+    let s = lab[0] - 0.0894841775 * lab[1] - 1.2914855480 * lab[2];
+
+    let l = l * l * l;
+    let m = m * m * m;
+    let s = s * s * s;
+
+    (
+        linear_to_srgb(4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s),
+        linear_to_srgb(-1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s),
+        linear_to_srgb(-0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s),
+    )
+}
+
+pub fn oklab_distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    let dl = a[0] - b[0];
+    let da = a[1] - b[1];
+    let db = a[2] - b[2];
+    (dl * dl + da * da + db * db).sqrt()
+}
+
+fn lab_f(t: f64) -> f64 {
+    const DELTA: f64 = 6.0 / 29.0;
+    if t > DELTA * DELTA * DELTA {
+        t.cbrt()
+    } else {
+        t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+    }
+}
+
+fn lab_f_inv(t: f64) -> f64 {
+    const DELTA: f64 = 6.0 / 29.0;
+    if t > DELTA {
+        t * t * t
+    } else {
+        3.0 * DELTA * DELTA * (t - 4.0 / 29.0)
+    }
+}
+
+pub fn srgb_to_lab(red: u8, grn: u8, blu: u8) -> [f64; 3] {
+    let r = srgb_to_linear(red);
+    let g = srgb_to_linear(grn);
+    let b = srgb_to_linear(blu);
+
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+
+    let fx = lab_f(x);
+    let fy = lab_f(y);
+    let fz = lab_f(z);
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+pub fn lab_to_srgb(lab: [f64; 3]) -> (u8, u8, u8) {
+    let fy = (lab[0] + 16.0) / 116.0;
+    let fx = fy + lab[1] / 500.0;
+    let fz = fy - lab[2] / 200.0;
+
+    let x = lab_f_inv(fx) * 0.95047;
+    let y = lab_f_inv(fy);
+    let z = lab_f_inv(fz) * 1.08883;
+
+    (
+        linear_to_srgb(3.2404542 * x - 1.5371385 * y - 0.4985314 * z),
+        linear_to_srgb(-0.9692660 * x + 1.8760108 * y + 0.0415560 * z),
+        linear_to_srgb(0.0556434 * x - 0.2040259 * y + 1.0572252 * z),
+    )
+}
+
+pub fn delta_e76(a: [f64; 3], b: [f64; 3]) -> f64 {
+    // CIE76 delta-E is the plain Euclidean distance in CIELAB, the same as 'oklab_distance'
+    oklab_distance(a, b)
+}
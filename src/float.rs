@@ -0,0 +1,72 @@
+// Float math for the builds without std, where f32 and f64 have no sqrt, powf and the like.
+// The methods forward to libm, with std the inherent methods are used instead.
+
+pub trait FloatExt {
+    fn sqrt(self) -> Self;
+    fn cbrt(self) -> Self;
+    fn powf(self, n: Self) -> Self;
+    fn powi(self, n: i32) -> Self;
+    fn exp(self) -> Self;
+    fn round(self) -> Self;
+    fn ceil(self) -> Self;
+}
+
+impl FloatExt for f64 {
+    fn sqrt(self) -> f64 {
+        libm::sqrt(self)
+    }
+
+    fn cbrt(self) -> f64 {
+        libm::cbrt(self)
+    }
+
+    fn powf(self, n: f64) -> f64 {
+        libm::pow(self, n)
+    }
+
+    fn powi(self, n: i32) -> f64 {
+        libm::pow(self, n as f64)
+    }
+
+    fn exp(self) -> f64 {
+        libm::exp(self)
+    }
+
+    fn round(self) -> f64 {
+        libm::round(self)
+    }
+
+    fn ceil(self) -> f64 {
+        libm::ceil(self)
+    }
+}
+
+impl FloatExt for f32 {
+    fn sqrt(self) -> f32 {
+        libm::sqrtf(self)
+    }
+
+    fn cbrt(self) -> f32 {
+        libm::cbrtf(self)
+    }
+
+    fn powf(self, n: f32) -> f32 {
+        libm::powf(self, n)
+    }
+
+    fn powi(self, n: i32) -> f32 {
+        libm::powf(self, n as f32)
+    }
+
+    fn exp(self) -> f32 {
+        libm::expf(self)
+    }
+
+    fn round(self) -> f32 {
+        libm::roundf(self)
+    }
+
+    fn ceil(self) -> f32 {
+        libm::ceilf(self)
+    }
+}
@@ -0,0 +1,55 @@
+// Parser for GIMP palette (.gpl) files:
+//
+// GIMP Palette
+// Name: Example
+// Columns: 4
+// #
+// 255   0   0 Red
+//   0 255   0 Green
+//
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    MissingHeader,
+    InvalidColor { line: usize },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::MissingHeader => write!(f, "missing 'GIMP Palette' header"),
+            ParseError::InvalidColor { line } => write!(f, "invalid color entry at line {}", line),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+pub fn parse(text: &str) -> Result<Vec<(u8, u8, u8)>, ParseError> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim() == "GIMP Palette" => {}
+        _ => return Err(ParseError::MissingHeader),
+    }
+
+    let mut colors = Vec::new();
+    for (i, line) in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("Name:") || line.starts_with("Columns:") {
+            continue;
+        }
+
+        // the color name is optional and may contain spaces, only the first three fields matter
+        let mut fields = line.split_whitespace().map(|v| v.parse::<u8>());
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(Ok(r)), Some(Ok(g)), Some(Ok(b))) => colors.push((r, g, b)),
+            _ => return Err(ParseError::InvalidColor { line: i + 1 }),
+        }
+    }
+    Ok(colors)
+}
@@ -0,0 +1,79 @@
+// A 3-d tree over the palette colors for the nearest color search in RGB.
+//
+// The tree is implicit: the entries are kept in a single array, the median of every range
+// is its node and the halves before and after it are the subtrees. The split axis cycles
+// through red, green and blue with the depth.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Entry {
+    color: [i32; 3],
+    key: u32, // packed r | g << 8 | b << 16, breaks the distance ties
+    index: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KdTree {
+    entries: Vec<Entry>,
+}
+
+impl KdTree {
+    pub fn new(colors: &[[u8; 3]]) -> KdTree {
+        let mut entries: Vec<Entry> = colors
+            .iter()
+            .enumerate()
+            .map(|(index, c)| Entry {
+                color: [c[0] as i32, c[1] as i32, c[2] as i32],
+                key: c[0] as u32 | (c[1] as u32) << 8 | (c[2] as u32) << 16,
+                index,
+            })
+            .collect();
+        build(&mut entries, 0);
+        KdTree { entries }
+    }
+
+    pub fn nearest(&self, color: [u8; 3]) -> Option<usize> {
+        // equally distant colors are resolved by the lower packed RGB value,
+        // the same colors by the lower index
+        let target = [color[0] as i32, color[1] as i32, color[2] as i32];
+        let mut best = None;
+        search(&self.entries, 0, target, &mut best);
+        best.map(|(_, _, index)| index)
+    }
+}
+
+fn build(entries: &mut [Entry], depth: usize) {
+    if entries.len() <= 1 {
+        return;
+    }
+    let axis = depth % 3;
+    let mid = entries.len() / 2;
+    entries.select_nth_unstable_by_key(mid, |e| e.color[axis]);
+    let (lower, upper) = entries.split_at_mut(mid);
+    build(lower, depth + 1);
+    build(&mut upper[1..], depth + 1);
+}
+
+fn search(entries: &[Entry], depth: usize, target: [i32; 3], best: &mut Option<(i32, u32, usize)>) {
+    if entries.is_empty() {
+        return;
+    }
+    let mid = entries.len() / 2;
+    let entry = entries[mid];
+    let d2 = (0..3).map(|c| (entry.color[c] - target[c]) * (entry.color[c] - target[c])).sum::<i32>();
+    if best.map_or(true, |b| (d2, entry.key, entry.index) < b) {
+        *best = Some((d2, entry.key, entry.index));
+    }
+
+    let axis = depth % 3;
+    let diff = target[axis] - entry.color[axis];
+    let (near, far) = if diff < 0 { (&entries[..mid], &entries[mid + 1..]) } else { (&entries[mid + 1..], &entries[..mid]) };
+    search(near, depth + 1, target, best);
+    // the colors beyond the split plane can only be as close as the plane itself,
+    // those at exactly the same distance may still win the tie
+    if best.map_or(true, |(best_d2, _, _)| diff * diff <= best_d2) {
+        search(far, depth + 1, target, best);
+    }
+}
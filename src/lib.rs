@@ -24,6 +24,326 @@
 // representative colors (color table).
 //
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("the 'libm' feature is required for the float math when building without 'std'");
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(feature = "libm")]
+extern crate libm;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "image")]
+extern crate image;
+
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap;
+#[cfg(not(feature = "std"))]
+use alloc::string::{String, ToString};
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+#[cfg(feature = "std")]
+use core::hash::{BuildHasherDefault, Hasher};
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+#[cfg(not(feature = "std"))]
+use float::FloatExt;
+
+use kdtree::KdTree;
+
+// scale of the saturation-weighted counts, keeps the weights of the rare colors apart
+const SATURATION_WEIGHT_SCALE: f64 = 256.0;
+
+// largest channel spread of a color that still counts as neutral gray
+const NEUTRAL_TOLERANCE: u8 = 8;
+
+// alphas of the same color at most this far apart are merged before premultiplying
+const ALPHA_MERGE_TOLERANCE: u32 = 32;
+
+// mapping distance at which the adaptive dithering diffuses the full error
+const ADAPTIVE_DITHER_FULL_DISTANCE: f32 = 32.0;
+
+// below this many pixels the histogram is built by sorting a copy of the pixels,
+// from this many on the colors are counted in a table over all 24 bit colors
+const SORTED_HISTOGRAM_MAX_PIXELS: usize = 1 << 12;
+const DENSE_HISTOGRAM_MIN_PIXELS: usize = 1 << 22;
+
+// enters a span around a quantization phase, compiled out without the tracing feature
+#[cfg(feature = "tracing")]
+macro_rules! phase_span {
+    ($name:expr) => {
+        tracing::debug_span!($name).entered()
+    };
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! phase_span {
+    ($name:expr) => {
+        ()
+    };
+}
+
+mod ase;
+mod bmp;
+pub mod color;
+#[cfg(not(feature = "std"))]
+mod float;
+mod gpl;
+mod kdtree;
+mod octree;
+#[cfg(test)]
+mod tests;
+
+pub use ase::AseError;
+pub use gpl::ParseError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Representative {
+    // count-weighted mean of the box colors
+    #[default]
+    Mean,
+    // count-weighted per-channel median of the box colors, robust against outliers
+    Median,
+}
+
+// byte order of the pixels in the u8 buffers
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PixelLayout {
+    #[default]
+    Rgba,
+    Bgra,
+    Argb,
+    Abgr,
+}
+
+impl PixelLayout {
+    fn pack(self, p: &[u8]) -> u32 {
+        // reorders the bytes of one pixel into the canonical r | g << 8 | b << 16 | a << 24
+        let rgba = match self {
+            PixelLayout::Rgba => [p[0], p[1], p[2], p[3]],
+            PixelLayout::Bgra => [p[2], p[1], p[0], p[3]],
+            PixelLayout::Argb => [p[1], p[2], p[3], p[0]],
+            PixelLayout::Abgr => [p[3], p[2], p[1], p[0]],
+        };
+        u32::from_le_bytes(rgba)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AlphaMode {
+    // the alpha byte of the pixels is discarded
+    #[default]
+    Ignore,
+    // the palette is built from RGB only, 'quantize_image' copies the alpha of every pixel
+    // onto its quantized color
+    Preserve,
+    // the pixels are premultiplied by their alpha before they are histogrammed and mapped,
+    // fully transparent pixels are left out of the histogram, 'quantize_image' keeps the alpha
+    Premultiply,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DistanceMetric {
+    // squared Euclidean distance of the RGB channels
+    #[default]
+    Euclidean,
+    // squared RGB distance with every channel difference weighted
+    WeightedRgb { wr: f32, wg: f32, wb: f32 },
+    // the "redmean" approximation, which weights the channels by the mean red of the two colors
+    Redmean,
+}
+
+impl DistanceMetric {
+    fn distance2(self, color: &ColorNode, red: u8, grn: u8, blu: u8) -> f64 {
+        let dr = color.red as f64 - red as f64;
+        let dg = color.grn as f64 - grn as f64;
+        let db = color.blu as f64 - blu as f64;
+        match self {
+            DistanceMetric::Euclidean => dr * dr + dg * dg + db * db,
+            DistanceMetric::WeightedRgb { wr, wg, wb } => wr as f64 * dr * dr + wg as f64 * dg * dg + wb as f64 * db * db,
+            DistanceMetric::Redmean => {
+                let rmean = (color.red as f64 + red as f64) / 2.0;
+                (2.0 + rmean / 256.0) * dr * dr + 4.0 * dg * dg + (2.0 + (255.0 - rmean) / 256.0) * db * db
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SplitStrategy {
+    // cut where the pixel count along the longest dimension reaches half of the box
+    #[default]
+    PopulationMedian,
+    // cut at the middle of the value range along the longest dimension, Heckbert's alternative
+    Midpoint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BoxSelection {
+    // split the box with the fewest splits so far, as in the original algorithm
+    #[default]
+    MinLevel,
+    // split the box whose colors deviate the most from their mean, weighted by the pixel counts
+    MaxVariance,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RemainderPolicy {
+    // stop as soon as no box can be split, even if k_max is not reached
+    #[default]
+    Stop,
+    // keep subdividing the box holding the most pixels by intensity until k_max is reached;
+    // as every box of two or more colors can be split, this only happens once the boxes
+    // below 'MmcqBuilder::min_box_count' have declined to split
+    SubdivideLargest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DitherMatrix {
+    Bayer2x2,
+    Bayer4x4,
+    Bayer8x8,
+}
+
+impl DitherMatrix {
+    fn size_bits(self) -> u32 {
+        match self {
+            DitherMatrix::Bayer2x2 => 1,
+            DitherMatrix::Bayer4x4 => 2,
+            DitherMatrix::Bayer8x8 => 3,
+        }
+    }
+
+    fn threshold(self, x: usize, y: usize) -> f32 {
+        // the Bayer index is the bit reversed interleave of x ^ y and y, it is
+        // centered around zero and lies within -0.5..0.5
+        let bits = self.size_bits();
+        let mut index = 0;
+        for bit in 0..bits {
+            let xb = ((x ^ y) >> bit) & 1;
+            let yb = (y >> bit) & 1;
+            index = (index << 2) | (xb << 1) | yb;
+        }
+        let cells = (1 << (2 * bits)) as f32;
+        (index as f32 + 0.5) / cells - 0.5
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DistanceSpace {
+    // plain RGB, as in the original algorithm
+    #[default]
+    Rgb,
+    // boxes are split and closest colors are searched in OKLab,
+    // representatives are converted back to sRGB
+    Oklab,
+    // boxes are split in RGB, but the closest colors are searched with every channel
+    // raised to the given power, which gives the dark colors more weight
+    Gamma(f32),
+    // boxes are split and closest colors are searched in CIELAB (D65),
+    // representatives are converted back to sRGB
+    Lab,
+}
+
+impl DistanceSpace {
+    fn splits_in_space(self) -> bool {
+        match self {
+            DistanceSpace::Oklab | DistanceSpace::Lab => true,
+            DistanceSpace::Rgb | DistanceSpace::Gamma(_) => false,
+        }
+    }
+
+    fn to_coords(self, red: u8, grn: u8, blu: u8) -> [f64; 3] {
+        match self {
+            DistanceSpace::Rgb => [red as f64, grn as f64, blu as f64],
+            DistanceSpace::Oklab => color::srgb_to_oklab(red, grn, blu),
+            DistanceSpace::Lab => color::srgb_to_lab(red, grn, blu),
+            DistanceSpace::Gamma(gamma) => {
+                let expand = |c: u8| (c as f64 / 255.0).powf(gamma as f64) * 255.0;
+                [expand(red), expand(grn), expand(blu)]
+            }
+        }
+    }
+
+    fn coords_to_rgb(self, coords: [f64; 3]) -> (u8, u8, u8) {
+        match self {
+            DistanceSpace::Rgb => (clamp_channel(coords[0] as f32), clamp_channel(coords[1] as f32), clamp_channel(coords[2] as f32)),
+            DistanceSpace::Oklab => color::oklab_to_srgb(coords),
+            DistanceSpace::Lab => color::lab_to_srgb(coords),
+            DistanceSpace::Gamma(gamma) => {
+                let compress = |c: f64| clamp_channel(((c / 255.0).max(0.0).powf(1.0 / gamma as f64) * 255.0) as f32);
+                (compress(coords[0]), compress(coords[1]), compress(coords[2]))
+            }
+        }
+    }
+
+    // Median cut works on 8-bit channels, so the colors are scaled into 0..255 within
+    // the space they are split in. The original color stays in the `rgb` field.
+    fn to_working(self, node: &ColorNode) -> ColorNode {
+        match self {
+            DistanceSpace::Rgb | DistanceSpace::Gamma(_) => *node,
+            DistanceSpace::Oklab => {
+                let lab = color::srgb_to_oklab(node.red, node.grn, node.blu);
+                ColorNode {
+                    rgb: node.rgb,
+                    red: clamp_channel((lab[0] * 255.0) as f32),
+                    grn: clamp_channel((lab[1] * 255.0 + 128.0) as f32),
+                    blu: clamp_channel((lab[2] * 255.0 + 128.0) as f32),
+                    cnt: node.cnt,
+                }
+            }
+            DistanceSpace::Lab => {
+                let lab = color::srgb_to_lab(node.red, node.grn, node.blu);
+                ColorNode {
+                    rgb: node.rgb,
+                    red: clamp_channel((lab[0] * 2.55) as f32),
+                    grn: clamp_channel((lab[1] + 128.0) as f32),
+                    blu: clamp_channel((lab[2] + 128.0) as f32),
+                    cnt: node.cnt,
+                }
+            }
+        }
+    }
+
+    fn working_to_rgb(self, red: u8, grn: u8, blu: u8) -> (u8, u8, u8) {
+        match self {
+            DistanceSpace::Rgb | DistanceSpace::Gamma(_) => (red, grn, blu),
+            DistanceSpace::Oklab => color::oklab_to_srgb([red as f64 / 255.0, (grn as f64 - 128.0) / 255.0, (blu as f64 - 128.0) / 255.0]),
+            DistanceSpace::Lab => color::lab_to_srgb([red as f64 / 2.55, grn as f64 - 128.0, blu as f64 - 128.0]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ColorSpace {
+    // boxes are split and closest colors are searched in RGB
+    #[default]
+    Rgb,
+    // the same in CIELAB, see 'DistanceSpace::Lab'
+    Lab,
+}
+
+impl From<ColorSpace> for DistanceSpace {
+    fn from(space: ColorSpace) -> DistanceSpace {
+        match space {
+            ColorSpace::Rgb => DistanceSpace::Rgb,
+            ColorSpace::Lab => DistanceSpace::Lab,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum ColorDimension {
     Red,
@@ -31,7 +351,50 @@ enum ColorDimension {
     Blue,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitAxis {
+    Red,
+    Green,
+    Blue,
+    // the box was subdivided by intensity, see 'RemainderPolicy::SubdivideLargest'
+    Intensity,
+}
+
+impl From<ColorDimension> for SplitAxis {
+    fn from(dim: ColorDimension) -> SplitAxis {
+        match dim {
+            ColorDimension::Red => SplitAxis::Red,
+            ColorDimension::Green => SplitAxis::Green,
+            ColorDimension::Blue => SplitAxis::Blue,
+        }
+    }
+}
+
+// Read-only view of a palette box. The ranges are inclusive and in the channels the boxes
+// were cut in, which are RGB unless the quantizer was built with another distance space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorRegion {
+    pub rmin: i32,
+    pub rmax: i32,
+    pub gmin: i32,
+    pub gmax: i32,
+    pub bmin: i32,
+    pub bmax: i32,
+    pub count: usize, // number of pixels in the box
+    pub level: isize, // number of splits that led to the box
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SplitEvent {
+    pub box_index: usize, // index of the split box among the boxes so far, it keeps the lower half
+    pub axis: SplitAxis, // the axis the box colors were sorted along
+    pub median: usize, // position of the cut within the sorted image colors, the first color of the upper half
+    pub lower_count: usize, // pixels in the lower half
+    pub upper_count: usize, // pixels in the upper half, appended as a new box
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ColorNode {
     pub rgb: u32,
     pub red: u8,
@@ -41,19 +404,30 @@ pub struct ColorNode {
 }
 
 impl ColorNode {
+    // The packed pixels keep the channels in the byte order of RGBA memory read as a
+    // little-endian u32: red is the low byte, then green and blue, the alpha byte is ignored.
+    fn unpack_rgb(rgb: u32) -> (u8, u8, u8) {
+        ((rgb & 0xFF) as u8, ((rgb & 0xFF00) >> 8) as u8, ((rgb & 0xFF0000) >> 16) as u8)
+    }
+
+    fn pack_rgb(red: u8, grn: u8, blu: u8) -> u32 {
+        red as u32 | ((grn as u32) << 8) | ((blu as u32) << 16)
+    }
+
     fn new_rgb(rgb: u32, cnt: usize) -> ColorNode {
+        let (red, grn, blu) = ColorNode::unpack_rgb(rgb);
         ColorNode {
             rgb: (rgb & 0xFFFFFF),
-            blu: ((rgb & 0xFF0000) >> 16) as u8,
-            grn: ((rgb & 0xFF00) >> 8) as u8,
-            red: (rgb & 0xFF) as u8,
+            red,
+            grn,
+            blu,
             cnt: cnt,
         }
     }
 
-    fn new_colors(red: u8, grn: u8, blu: u8, cnt: usize) -> ColorNode {
+    pub fn new_colors(red: u8, grn: u8, blu: u8, cnt: usize) -> ColorNode {
         ColorNode {
-            rgb: ((red as u32 & 0xff) << 16) | ((grn as u32 & 0xff) << 8) | blu as u32 & 0xff,
+            rgb: ColorNode::pack_rgb(red, grn, blu),
             red: red,
             grn: grn,
             blu: blu,
@@ -69,12 +443,45 @@ impl ColorNode {
         let db = self.blu as i32 - blu as i32;
         return dr * dr + dg * dg + db * db;
     }
+
+    pub fn to_hex(&self) -> String {
+        self.to_string()
+    }
+
+    pub fn perceptually_equal(&self, other: &ColorNode, delta_e_threshold: f64) -> bool {
+        let lab = color::srgb_to_lab(self.red, self.grn, self.blu);
+        let other_lab = color::srgb_to_lab(other.red, other.grn, other.blu);
+        color::delta_e76(lab, other_lab) <= delta_e_threshold
+    }
+
+    fn saturation(&self) -> f64 {
+        // HSV saturation
+        let max = self.red.max(self.grn).max(self.blu);
+        let min = self.red.min(self.grn).min(self.blu);
+        if max == 0 {
+            0.0
+        } else {
+            (max - min) as f64 / max as f64
+        }
+    }
+
+    fn distance2_packed(&self, rgb: u32) -> i32 {
+        let (red, grn, blu) = ColorNode::unpack_rgb(rgb);
+        self.distance2(red, grn, blu)
+    }
+}
+
+impl fmt::Display for ColorNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // CSS style #rrggbb, the count is left out
+        write!(f, "#{:02x}{:02x}{:02x}", self.red, self.grn, self.blu)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
 struct ColorBox {
     lower: usize, // lower index into 'imageColors'
-    upper: usize, // upper index into 'imageColors'
+    upper: usize, // upper index into 'imageColors', exclusive
     level: isize, // split level o this color box
     count: usize, // number of pixels represented by thos color box
     rmin: i32,
@@ -83,6 +490,7 @@ struct ColorBox {
     gmax: i32, // range of contained colors in green dimension
     bmin: i32,
     bmax: i32, // range of contained colors in blue dimension
+    frozen: bool, // declined a split, one of its halves would hold too few pixels
 }
 
 impl ColorBox {
@@ -104,6 +512,10 @@ impl ColorBox {
         self.upper - self.lower
     }
 
+    fn is_splittable(&self) -> bool {
+        !self.frozen && self.color_count() >= 2
+    }
+
     fn trim(&mut self, colors: &Vec<ColorNode>) {
         // recompute the boundaries of this color box
         self.rmin = 255;
@@ -140,26 +552,56 @@ impl ColorBox {
         }
     }
 
-    fn split_box(&mut self, colors: &mut Vec<ColorNode>) -> Option<ColorBox> {
+    fn split_box(&mut self, colors: &mut Vec<ColorNode>, strategy: SplitStrategy, min_count: usize) -> Option<ColorBox> {
         if self.color_count() < 2 {
             None // this box cannot be split
         } else {
             // find longest dimension of this box:
             let dim = self.get_longest_color_dimension();
 
-            // find median along dim
-            let med = self.find_median(dim, colors);
+            // find the last color of the lower half along dim, both halves keep at least one color
+            let med = match strategy {
+                SplitStrategy::PopulationMedian => self.find_median(dim, colors),
+                SplitStrategy::Midpoint => self.find_midpoint(dim, colors),
+            };
+            let mut med = med.min(self.upper - 2);
+
+            // the cut is moved inwards until both halves stand for at least min_count pixels,
+            // if there is no such cut the box is not split
+            let mut lower_count: usize = colors[self.lower..=med].iter().map(|c| c.cnt).sum();
+            while self.count - lower_count < min_count && med > self.lower {
+                lower_count -= colors[med].cnt;
+                med -= 1;
+            }
+            while lower_count < min_count && med + 2 < self.upper {
+                med += 1;
+                lower_count += colors[med].cnt;
+            }
+            if lower_count < min_count || self.count - lower_count < min_count {
+                return None;
+            }
 
             // now split this box at the median return the resulting new box.
             let next_level = self.level + 1;
             let new_box = ColorBox::new(med + 1, self.upper, next_level, colors);
-            self.upper = med;
+            self.upper = med + 1;
             self.level = next_level;
             self.trim(colors);
             Some(new_box)
         }
     }
 
+    fn subdivide_by_intensity(&mut self, colors: &mut Vec<ColorNode>) -> ColorBox {
+        // split the box into a darker and a brighter half, regardless of its level
+        colors[self.lower..self.upper].sort_by_key(|c| 299 * c.red as u32 + 587 * c.grn as u32 + 114 * c.blu as u32);
+        let mid = self.lower + self.color_count() / 2;
+        let new_box = ColorBox::new(mid, self.upper, self.level + 1, colors);
+        self.upper = mid;
+        self.level += 1;
+        self.trim(colors);
+        new_box
+    }
+
     fn get_longest_color_dimension(&self) -> ColorDimension {
         let r_length = self.rmax - self.rmin;
         let g_length = self.gmax - self.gmin;
@@ -174,13 +616,29 @@ impl ColorBox {
         }
     }
 
-    fn find_median(&self, dim: ColorDimension, colors: &mut Vec<ColorNode>) -> usize {
+    fn sort_along(&self, dim: ColorDimension, colors: &mut [ColorNode]) {
         // sort color in this box along dimension dim:
-        match dim {
-            ColorDimension::Red => colors[self.lower..(self.upper + 1)].sort_by(|a, b| a.red.cmp(&b.red)),
-            ColorDimension::Green => colors[self.lower..(self.upper + 1)].sort_by(|a, b| a.grn.cmp(&b.grn)),
-            ColorDimension::Blue => colors[self.lower..(self.upper + 1)].sort_by(|a, b| a.blu.cmp(&b.blu)),
+        match dim {
+            ColorDimension::Red => colors[self.lower..self.upper].sort_by(|a, b| a.red.cmp(&b.red)),
+            ColorDimension::Green => colors[self.lower..self.upper].sort_by(|a, b| a.grn.cmp(&b.grn)),
+            ColorDimension::Blue => colors[self.lower..self.upper].sort_by(|a, b| a.blu.cmp(&b.blu)),
         }
+    }
+
+    fn find_midpoint(&self, dim: ColorDimension, colors: &mut [ColorNode]) -> usize {
+        // last color at or below the middle of the box range along dim
+        self.sort_along(dim, colors);
+        let (value, mid): (fn(&ColorNode) -> u8, i32) = match dim {
+            ColorDimension::Red => (|c| c.red, (self.rmin + self.rmax) / 2),
+            ColorDimension::Green => (|c| c.grn, (self.gmin + self.gmax) / 2),
+            ColorDimension::Blue => (|c| c.blu, (self.bmin + self.bmax) / 2),
+        };
+        let above = colors[self.lower..self.upper].iter().position(|c| value(c) as i32 > mid).unwrap_or(self.color_count());
+        (self.lower + above).max(self.lower + 1) - 1
+    }
+
+    fn find_median(&self, dim: ColorDimension, colors: &mut Vec<ColorNode>) -> usize {
+        self.sort_along(dim, colors);
 
         // find the median point:
         let half = self.count / 2;
@@ -196,17 +654,22 @@ impl ColorBox {
     }
 
     fn get_average_color(&self, colors: &mut Vec<ColorNode>) -> ColorNode {
-        let mut r_sum = 0;
-        let mut g_sum = 0;
-        let mut b_sum = 0;
+        // the channel sums are u64, a 32 bit usize overflows at 16M pixels of white
+        let mut r_sum = 0u64;
+        let mut g_sum = 0u64;
+        let mut b_sum = 0u64;
         let mut n = 0usize;
         for i in self.lower..self.upper {
             let ci = colors[i];
-            let cnt = ci.cnt;
-            r_sum = r_sum + cnt * ci.red as usize;
-            g_sum = g_sum + cnt * ci.grn as usize;
-            b_sum = b_sum + cnt * ci.blu as usize;
-            n = n + cnt;
+            let cnt = ci.cnt as u64;
+            r_sum = r_sum + cnt * ci.red as u64;
+            g_sum = g_sum + cnt * ci.grn as u64;
+            b_sum = b_sum + cnt * ci.blu as u64;
+            n = n + ci.cnt;
+        }
+        if n == 0 {
+            // an empty box has no average, it stands for no pixels
+            return ColorNode::new_colors(0, 0, 0, 0);
         }
         // let nd = n as f64;
         let avg_red = (0.5 + r_sum as f64 / n as f64) as u8;
@@ -214,8 +677,304 @@ impl ColorBox {
         let avg_blu = (0.5 + b_sum as f64 / n as f64) as u8;
         ColorNode::new_colors(avg_red, avg_grn, avg_blu, n)
     }
+
+    fn get_median_color(&self, colors: &[ColorNode]) -> ColorNode {
+        let mut r_hist = [0usize; 256];
+        let mut g_hist = [0usize; 256];
+        let mut b_hist = [0usize; 256];
+        let mut n = 0usize;
+        for ci in &colors[self.lower..self.upper] {
+            r_hist[ci.red as usize] += ci.cnt;
+            g_hist[ci.grn as usize] += ci.cnt;
+            b_hist[ci.blu as usize] += ci.cnt;
+            n += ci.cnt;
+        }
+        ColorNode::new_colors(weighted_median(&r_hist, n), weighted_median(&g_hist, n), weighted_median(&b_hist, n), n)
+    }
+
+    fn get_average_color_in(&self, colors: &[ColorNode], space: DistanceSpace) -> ColorNode {
+        // the colors hold working channels, so the averaging goes over the original colors
+        let mut sum = [0f64; 3];
+        let mut n = 0usize;
+        for ci in &colors[self.lower..self.upper] {
+            let orig = ColorNode::new_rgb(ci.rgb, ci.cnt);
+            let coords = space.to_coords(orig.red, orig.grn, orig.blu);
+            for c in 0..3 {
+                sum[c] += coords[c] * ci.cnt as f64;
+            }
+            n += ci.cnt;
+        }
+        if n == 0 {
+            return ColorNode::new_colors(0, 0, 0, 0);
+        }
+        let (red, grn, blu) = space.coords_to_rgb([sum[0] / n as f64, sum[1] / n as f64, sum[2] / n as f64]);
+        ColorNode::new_colors(red, grn, blu, n)
+    }
+
+    fn get_spread(&self, colors: &[ColorNode]) -> [f64; 3] {
+        let members = || colors[self.lower..self.upper].iter().map(|ci| ColorNode::new_rgb(ci.rgb, ci.cnt));
+        let n = members().map(|ci| ci.cnt).sum::<usize>() as f64;
+        if n == 0.0 {
+            return [0.0; 3];
+        }
+
+        let mut mean = [0f64; 3];
+        for ci in members() {
+            mean[0] += ci.red as f64 * ci.cnt as f64 / n;
+            mean[1] += ci.grn as f64 * ci.cnt as f64 / n;
+            mean[2] += ci.blu as f64 * ci.cnt as f64 / n;
+        }
+        let mut var = [0f64; 3];
+        for ci in members() {
+            var[0] += (ci.red as f64 - mean[0]).powi(2) * ci.cnt as f64 / n;
+            var[1] += (ci.grn as f64 - mean[1]).powi(2) * ci.cnt as f64 / n;
+            var[2] += (ci.blu as f64 - mean[2]).powi(2) * ci.cnt as f64 / n;
+        }
+        [var[0].sqrt(), var[1].sqrt(), var[2].sqrt()]
+    }
+
+    fn get_weighted_variance(&self, colors: &[ColorNode]) -> f64 {
+        // sum of the squared distances of the pixels to their mean, in the channels the box is cut in
+        let members = &colors[self.lower..self.upper];
+        let n = self.count as f64;
+        if n == 0.0 {
+            return 0.0;
+        }
+
+        let mut mean = [0f64; 3];
+        for ci in members {
+            mean[0] += ci.red as f64 * ci.cnt as f64 / n;
+            mean[1] += ci.grn as f64 * ci.cnt as f64 / n;
+            mean[2] += ci.blu as f64 * ci.cnt as f64 / n;
+        }
+        members
+            .iter()
+            .map(|ci| ((ci.red as f64 - mean[0]).powi(2) + (ci.grn as f64 - mean[1]).powi(2) + (ci.blu as f64 - mean[2]).powi(2)) * ci.cnt as f64)
+            .sum()
+    }
+
+    fn get_representative_color(&self, colors: &mut Vec<ColorNode>, representative: Representative, space: DistanceSpace) -> ColorNode {
+        match representative {
+            Representative::Mean if space.splits_in_space() => self.get_average_color_in(colors, space),
+            Representative::Mean => self.get_average_color(colors),
+            Representative::Median => {
+                let median = self.get_median_color(colors);
+                let (red, grn, blu) = space.working_to_rgb(median.red, median.grn, median.blu);
+                ColorNode::new_colors(red, grn, blu, median.cnt)
+            }
+        }
+    }
+}
+
+// SplitMix64, a small generator that gives the same sequence for the same seed on every platform
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+fn bilateral_filter(pixels: &[u32], width: usize, height: usize, sigma_space: f32, sigma_color: f32) -> Vec<u32> {
+    // every channel byte is filtered the same way, so the channel order does not matter;
+    // the neighbors are weighted by both their distance and their color difference
+    assert_eq!(pixels.len(), width * height);
+    let channels = |rgb: u32| [(rgb & 0xFF) as f32, ((rgb >> 8) & 0xFF) as f32, ((rgb >> 16) & 0xFF) as f32];
+    let radius = (2.0 * sigma_space).ceil().max(1.0) as isize;
+    let space_div = (2.0 * sigma_space * sigma_space).max(f32::MIN_POSITIVE);
+    let color_div = (2.0 * sigma_color * sigma_color).max(f32::MIN_POSITIVE);
+
+    let mut filtered = Vec::with_capacity(pixels.len());
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let center = channels(pixels[y as usize * width + x as usize]);
+            let mut sum = [0f32; 3];
+            let mut weights = 0f32;
+            for ny in (y - radius).max(0)..(y + radius + 1).min(height as isize) {
+                for nx in (x - radius).max(0)..(x + radius + 1).min(width as isize) {
+                    let neighbor = channels(pixels[ny as usize * width + nx as usize]);
+                    let d2_space = ((nx - x) * (nx - x) + (ny - y) * (ny - y)) as f32;
+                    let d2_color = (0..3).map(|c| (neighbor[c] - center[c]) * (neighbor[c] - center[c])).sum::<f32>();
+                    let weight = (-d2_space / space_div - d2_color / color_div).exp();
+                    for c in 0..3 {
+                        sum[c] += neighbor[c] * weight;
+                    }
+                    weights += weight;
+                }
+            }
+            let value = |c: usize| clamp_channel(sum[c] / weights) as u32;
+            filtered.push(value(0) | (value(1) << 8) | (value(2) << 16));
+        }
+    }
+    filtered
+}
+
+fn box_downscale(pixels: &[u32], width: usize, height: usize, max_dim: usize) -> Vec<u32> {
+    // every block of block x block pixels becomes its mean color, the blocks on the right and
+    // bottom edges may be smaller; the block size is the smallest one that brings the longer
+    // side down to max_dim, the alpha components are dropped. Details smaller than a block are
+    // smeared into the mean of their block, only those covering whole blocks keep their color.
+    assert_eq!(pixels.len(), width * height, "pixel count does not match the image size");
+    let block = width.max(height).div_ceil(max_dim.max(1)).max(1);
+    let (thumb_width, thumb_height) = (width.div_ceil(block), height.div_ceil(block));
+    let mut sums = vec![[0u64; 4]; thumb_width * thumb_height]; // red, green, blue, pixels
+    for (i, &rgb) in pixels.iter().enumerate() {
+        let (x, y) = (i % width, i / width);
+        let (red, grn, blu) = ColorNode::unpack_rgb(rgb);
+        let sum = &mut sums[(y / block) * thumb_width + x / block];
+        sum[0] += red as u64;
+        sum[1] += grn as u64;
+        sum[2] += blu as u64;
+        sum[3] += 1;
+    }
+    sums.iter()
+        .map(|sum| {
+            let mean = |value: u64| ((value + sum[3] / 2) / sum[3]) as u8;
+            ColorNode::pack_rgb(mean(sum[0]), mean(sum[1]), mean(sum[2]))
+        })
+        .collect()
+}
+
+fn reduce_channel_depth(value: u8, bits: u8) -> u8 {
+    // rounds the value to the closest one representable with the given number of bits,
+    // scaled back to 0..255
+    if bits >= 8 {
+        return value;
+    }
+    if bits == 0 {
+        return 0;
+    }
+    let max = (1u32 << bits) - 1;
+    let level = (value as u32 * max + 127) / 255;
+    ((level * 255 + max / 2) / max) as u8
+}
+
+fn luminance(rgb: u32) -> f64 {
+    // Rec. 601 luma
+    let (red, grn, blu) = ColorNode::unpack_rgb(rgb);
+    0.299 * red as f64 + 0.587 * grn as f64 + 0.114 * blu as f64
+}
+
+fn pack_pixel_bytes(pixels: &[u8], layout: PixelLayout) -> Vec<u32> {
+    // every byte quadruple is packed as r | g << 8 | b << 16 | a << 24, whatever the
+    // alignment of the buffer and the endianness of the platform
+    pixels.chunks(4).map(|p| layout.pack(p)).collect()
+}
+
+fn premultiply(rgba: u32) -> u32 {
+    let alpha = rgba >> 24;
+    let (red, grn, blu) = ColorNode::unpack_rgb(rgba);
+    let scale = |value: u8| ((value as u32 * alpha + 127) / 255) as u8;
+    ColorNode::pack_rgb(scale(red), scale(grn), scale(blu)) | (alpha << 24)
+}
+
+fn premultiply_merged(pixels: &[u32]) -> Vec<u32> {
+    // Premultiplies the visible pixels. The alphas of every color are grouped into runs
+    // spanning no more than ALPHA_MERGE_TOLERANCE from their first alpha, and all pixels
+    // of a run are premultiplied with its mean alpha, so nearly equal alphas of a color do
+    // not spread it over several histogram entries, while a long alpha ramp still does.
+    let mut visible: Vec<u32> = pixels.iter().cloned().filter(|&rgba| rgba >> 24 > 0).collect();
+    visible.sort_unstable_by_key(|&rgba| (rgba & 0xFFFFFF, rgba >> 24));
+    let mut premultiplied = Vec::with_capacity(visible.len());
+    let mut start = 0;
+    for i in 1..=visible.len() {
+        if i < visible.len() && visible[i] & 0xFFFFFF == visible[start] & 0xFFFFFF && (visible[i] >> 24) - (visible[start] >> 24) <= ALPHA_MERGE_TOLERANCE {
+            continue;
+        }
+        let run = &visible[start..i];
+        let n = run.len() as u64;
+        let alpha = (run.iter().map(|&rgba| (rgba >> 24) as u64).sum::<u64>() + n / 2) / n;
+        let rgba = premultiply((visible[start] & 0xFFFFFF) | (alpha as u32) << 24);
+        premultiplied.extend(core::iter::repeat(rgba).take(run.len()));
+        start = i;
+    }
+    premultiplied
+}
+
+fn channel_from_u16(value: u16) -> u8 {
+    // scales 0..65535 down to 0..255 rounding to the closest value, a plain shift
+    // would truncate and shift the whole image towards black
+    ((value as u32 * 255 + 32767) / 65535) as u8
+}
+
+fn check_rows(len: usize, width: usize) -> Result<(), MmcqError> {
+    // the pixels have to make up whole rows of a nonzero width
+    if width == 0 || len % width != 0 {
+        return Err(MmcqError::NotRowAligned { len, width });
+    }
+    Ok(())
+}
+
+fn diffuse_floyd_steinberg<F: Fn(usize) -> bool>(errors: &mut [[f32; 3]], i: usize, width: usize, diff: [f32; 3], accepts: F) {
+    // spreads the error of pixel i over the neighbours not mapped yet, with the
+    // Floyd-Steinberg weights 7/16, 3/16, 5/16 and 1/16
+    let x = i % width;
+    let mut diffuse = |j: usize, weight: f32| {
+        if j < errors.len() && accepts(j) {
+            for c in 0..3 {
+                errors[j][c] += diff[c] * weight;
+            }
+        }
+    };
+    if x + 1 < width {
+        diffuse(i + 1, 7.0 / 16.0);
+        diffuse(i + width + 1, 1.0 / 16.0);
+    }
+    if x > 0 {
+        diffuse(i + width - 1, 3.0 / 16.0);
+    }
+    diffuse(i + width, 5.0 / 16.0);
+}
+
+fn clamp_channel(value: f32) -> u8 {
+    (value + 0.5).clamp(0.0, 255.0) as u8
+}
+
+fn weighted_median(hist: &[usize; 256], n: usize) -> u8 {
+    // first channel value at which the cumulative count reaches half of the total
+    let mut acc = 0;
+    for (value, cnt) in hist.iter().enumerate() {
+        acc += cnt;
+        if n > 0 && acc * 2 >= n {
+            return value as u8;
+        }
+    }
+    0
+}
+
+// The histogram keys are 24 bit colors, a single multiplication spreads them well enough
+// and is much cheaper than the default SipHash.
+#[cfg(feature = "std")]
+#[derive(Default)]
+struct ColorHasher(u64);
+
+#[cfg(feature = "std")]
+impl Hasher for ColorHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0.rotate_left(8) ^ b as u64).wrapping_mul(0x9E3779B97F4A7C15);
+        }
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.0 = (value as u64).wrapping_mul(0x9E3779B97F4A7C15);
+    }
 }
 
+// maps keyed by the colors, hashed with std and ordered without it
+#[cfg(feature = "std")]
+type ColorMap<K, V> = HashMap<K, V, BuildHasherDefault<ColorHasher>>;
+#[cfg(not(feature = "std"))]
+type ColorMap<K, V> = BTreeMap<K, V>;
+
 struct ColorHistogram {
     color_array: Vec<u32>,
     count_array: Vec<usize>,
@@ -230,6 +989,40 @@ impl ColorHistogram {
     }
 
     pub fn new_pixels(pixels_orig: &[u32]) -> ColorHistogram {
+        // the colors come out in ascending order whichever way they are counted
+        let n = pixels_orig.len();
+        if n < SORTED_HISTOGRAM_MAX_PIXELS {
+            return ColorHistogram::new_pixels_sorted(pixels_orig);
+        }
+
+        if n >= DENSE_HISTOGRAM_MIN_PIXELS && n <= u32::MAX as usize {
+            return ColorHistogram::new_pixels_dense(pixels_orig);
+        }
+        ColorHistogram::new_pixels_hashed(pixels_orig)
+    }
+
+    fn new_pixels_dense(pixels_orig: &[u32]) -> ColorHistogram {
+        // a counter for every 24 bit color, the counts must fit into u32
+        let mut counts = vec![0u32; 1 << 24];
+        for &rgba in pixels_orig {
+            counts[(rgba & 0xFFFFFF) as usize] += 1;
+        }
+        let (color_array, count_array) = counts.iter().enumerate().filter(|&(_, &cnt)| cnt > 0).map(|(rgb, &cnt)| (rgb as u32, cnt as usize)).unzip();
+        ColorHistogram::new(color_array, count_array)
+    }
+
+    fn new_pixels_hashed(pixels_orig: &[u32]) -> ColorHistogram {
+        let mut counts: ColorMap<u32, usize> = ColorMap::default();
+        for &rgba in pixels_orig {
+            *counts.entry(rgba & 0xFFFFFF).or_insert(0) += 1;
+        }
+        let mut entries: Vec<(u32, usize)> = counts.into_iter().collect();
+        entries.sort_unstable_by_key(|&(rgb, _)| rgb);
+        let (color_array, count_array) = entries.into_iter().unzip();
+        ColorHistogram::new(color_array, count_array)
+    }
+
+    fn new_pixels_sorted(pixels_orig: &[u32]) -> ColorHistogram {
         let n = pixels_orig.len();
         let mut pixels_copy = Vec::with_capacity(n);
         for i in 0..n {
@@ -238,23 +1031,11 @@ impl ColorHistogram {
         }
         pixels_copy.sort();
 
-        // count unique colors:
+        // tabulate and count unique colors in a single pass over the sorted pixels:
+        let mut color_array = Vec::new();
+        let mut count_array = Vec::new();
         let mut k = 0; // current color index
-        let mut inited = false;
         let mut cur_color = 0;
-        for i in 0..pixels_copy.len() {
-            if pixels_copy[i] != cur_color || !inited {
-                cur_color = pixels_copy[i];
-                k += 1;
-                inited = true;
-            }
-        }
-
-        // tabulate and count unique colors:
-        let mut color_array = Vec::with_capacity(k);
-        let mut count_array = Vec::with_capacity(k);
-        k = 0;	// current color index
-        cur_color = 0;
         let mut inited = false;
         for i in 0..pixels_copy.len() {
             if pixels_copy[i] != cur_color || !inited {
@@ -272,47 +1053,1279 @@ impl ColorHistogram {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum MmcqError {
+    // the pixel count is not a multiple of the row width
+    NotRowAligned { len: usize, width: usize },
+    // the byte count is not a multiple of 4, so the bytes are no whole RGBA pixels
+    NotRgbaAligned { len: usize },
+    // the padded rows make the image too large for the 32-bit sizes of a BMP file
+    TooLargeForBmp { width: u32, height: u32 },
+    // the sample rate is NaN or outside of 0 < rate <= 1
+    InvalidSampleRate { rate: f32 },
+}
+
+impl fmt::Display for MmcqError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MmcqError::NotRowAligned { len, width } => write!(f, "{} pixels do not split into rows of {}", len, width),
+            MmcqError::NotRgbaAligned { len } => write!(f, "{} bytes are not a whole number of RGBA pixels", len),
+            MmcqError::TooLargeForBmp { width, height } => write!(f, "a {}x{} image is too large for a BMP file", width, height),
+            MmcqError::InvalidSampleRate { rate } => write!(f, "sample rate {} is not within (0, 1]", rate),
+        }
+    }
+}
+
+impl Error for MmcqError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+// offset of the tile's palette within the atlas palette and the tile's indices relative to it
+pub type AtlasTile = (usize, Vec<u8>);
+
+// lower and upper corners of an inclusive RGB box and the number of colors it gets
+pub type RegionBudget = ((u8, u8, u8), (u8, u8, u8), u32);
+
+// palette colors, most used first, that can be stored and turned back into a quantizer
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Palette(pub Vec<ColorNode>);
+
 pub struct MMCQ {
     image_colors: Vec<ColorNode>,
     quant_colors: Vec<ColorNode>,
+    quant_boxes: Vec<ColorBox>, // boxes of 'image_colors' the palette colors stand for, if built from an image
+    space: DistanceSpace,
+    quant_coords: Vec<[f64; 3]>, // palette colors within 'space', unless it is RGB
+    quant_tree: KdTree, // nearest color search over the palette, if 'space' is RGB
+    alpha_mode: AlphaMode,
+    metric: DistanceMetric,
 }
 
 impl MMCQ {
     pub fn from_pixels_u8_rgba(pixels: &[u8], k_max: u32) -> MMCQ {
-        let pixels = unsafe { ::std::slice::from_raw_parts::<u32>(::std::mem::transmute(&pixels[0]), pixels.len() / 4) };
+        // panics if the buffer does not consist of whole RGBA pixels
+        MMCQ::try_from_pixels_u8_rgba(pixels, k_max).unwrap()
+    }
 
-        MMCQ::from_pixels_u32_rgba(pixels, k_max)
+    pub fn from_canvas_rgba(pixels: &[u8], k_max: u32) -> MMCQ {
+        // the bytes of a browser 'ImageData', row by row with every pixel as r, g, b, a;
+        // the alpha is ignored, panics if the buffer does not consist of whole pixels
+        MMCQ::from_pixels_u8(pixels, PixelLayout::Rgba, k_max)
     }
 
-    pub fn from_pixels_u32_rgba(pixels: &[u32], k_max: u32) -> MMCQ {
-        let mut m = MMCQ {
-            image_colors: Vec::new(),
-            quant_colors: Vec::new(),
+    pub fn from_pixels_u8(pixels: &[u8], layout: PixelLayout, k_max: u32) -> MMCQ {
+        // panics if the buffer does not consist of whole 4 byte pixels
+        assert!(pixels.len() % 4 == 0, "{}", MmcqError::NotRgbaAligned { len: pixels.len() });
+        MMCQ::from_pixels_u32_rgba(&pack_pixel_bytes(pixels, layout), k_max)
+    }
+
+    pub fn try_from_pixels_u8_rgba(pixels: &[u8], k_max: u32) -> Result<MMCQ, MmcqError> {
+        if pixels.len() % 4 != 0 {
+            return Err(MmcqError::NotRgbaAligned { len: pixels.len() });
+        }
+        Ok(MmcqBuilder::new().max_colors(k_max).build_from_u8_rgba(pixels))
+    }
+
+    pub fn from_rgb_iter<I: IntoIterator<Item = (u8, u8, u8)>>(iter: I, k_max: u32) -> MMCQ {
+        let pixels: Vec<u32> = iter.into_iter().map(|(red, grn, blu)| ColorNode::pack_rgb(red, grn, blu)).collect();
+        MMCQ::from_pixels_u32_rgba(&pixels, k_max)
+    }
+
+    pub fn from_pixels_u16_rgb(pixels: &[[u16; 3]], k_max: u32) -> MMCQ {
+        // 16 bit channels are rounded to the closest 8 bit value, the palette is built from the result
+        let pixels: Vec<u32> = pixels.iter().map(|&[red, grn, blu]| ColorNode::pack_rgb(channel_from_u16(red), channel_from_u16(grn), channel_from_u16(blu))).collect();
+        MMCQ::from_pixels_u32_rgba(&pixels, k_max)
+    }
+
+    pub fn from_luma(pixels: &[u8], k_max: u32) -> MMCQ {
+        // every byte is a gray pixel, the palette is ordered by intensity, darkest first
+        let gray: Vec<u32> = pixels.iter().map(|&value| ColorNode::pack_rgb(value, value, value)).collect();
+        let mut m = MMCQ::from_pixels_u32_rgba(&gray, k_max);
+        let mut entries: Vec<_> = m.quant_colors.drain(..).zip(m.quant_boxes.drain(..)).collect();
+        entries.sort_by_key(|e| e.0.red);
+        let (palette, boxes) = entries.into_iter().unzip();
+        m.quant_colors = palette;
+        m.quant_boxes = boxes;
+        m.update_palette_coords();
+        m
+    }
+
+    #[cfg(feature = "image")]
+    pub fn from_rgba_image(img: &image::RgbaImage, k_max: u32) -> MMCQ {
+        MMCQ::from_pixels_u8_rgba(img, k_max)
+    }
+
+    #[cfg(feature = "image")]
+    pub fn from_rgb_image(img: &image::RgbImage, k_max: u32) -> MMCQ {
+        MMCQ::from_rgb_iter(img.chunks(3).map(|p| (p[0], p[1], p[2])), k_max)
+    }
+
+    pub fn from_pixels_u32_rgba(pixels: &[u32], k_max: u32) -> MMCQ {
+        MmcqBuilder::new().max_colors(k_max).build_from_u32(pixels)
+    }
+
+    pub fn from_pixels_u32_rgba_sampled(pixels: &[u32], k_max: u32, sample_rate: f32, seed: u64) -> MMCQ {
+        // panics if the sample rate is not within 0 < sample_rate <= 1
+        MMCQ::try_from_pixels_u32_rgba_sampled(pixels, k_max, sample_rate, seed).unwrap()
+    }
+
+    pub fn try_from_pixels_u32_rgba_sampled(pixels: &[u32], k_max: u32, sample_rate: f32, seed: u64) -> Result<MMCQ, MmcqError> {
+        // every pixel is kept with the probability sample_rate, so the same seed always picks
+        // the same pixels; a rate of 1.0 uses the whole image
+        if !(sample_rate > 0.0 && sample_rate <= 1.0) {
+            return Err(MmcqError::InvalidSampleRate { rate: sample_rate });
+        }
+        if sample_rate == 1.0 {
+            return Ok(MMCQ::from_pixels_u32_rgba(pixels, k_max));
+        }
+        let mut rng = SplitMix64(seed);
+        let threshold = (sample_rate as f64 * (1u64 << 53) as f64) as u64;
+        let sample: Vec<u32> = pixels.iter().copied().filter(|_| rng.next_u64() >> 11 < threshold).collect();
+        Ok(MMCQ::from_pixels_u32_rgba(&sample, k_max))
+    }
+
+    #[cfg(feature = "image")]
+    pub fn quantize_rgba_image(&self, img: &image::RgbaImage) -> image::RgbaImage {
+        // the colors are mapped onto the palette, the image keeps its own alpha whatever the alpha mode
+        let bytes = pack_pixel_bytes(img, PixelLayout::Rgba)
+            .into_iter()
+            .flat_map(|rgba| (self.find_closest_color(self.prepare_pixel(rgba)).rgb | (rgba & 0xFF000000)).to_le_bytes())
+            .collect();
+        let (width, height) = img.dimensions();
+        image::ImageBuffer::from_raw(width, height, bytes).expect("the buffer has the size of the image")
+    }
+
+    pub fn quantize_luma(&self, pixels: &[u8]) -> Vec<u8> {
+        // intensity of the closest palette color of every gray pixel
+        let mut levels = [0u8; 256];
+        for (value, level) in levels.iter_mut().enumerate() {
+            let value = value as u8;
+            *level = luminance(self.find_closest_color(ColorNode::pack_rgb(value, value, value)).rgb).round() as u8;
+        }
+        pixels.iter().map(|&value| levels[value as usize]).collect()
+    }
+
+    pub fn quantize_in_place_returning_palette(pixels: &mut [u32], k_max: u32) -> Vec<(u8, u8, u8)> {
+        // builds the palette from the buffer and remaps the buffer onto it, no copy of the image is made
+        let m = MMCQ::from_pixels_u32_rgba(pixels, k_max);
+        for rgb in pixels.iter_mut() {
+            *rgb = m.find_closest_color(*rgb).rgb;
+        }
+        m.palette_rgb()
+    }
+
+    pub fn from_pixels_u32_rgba_traced(pixels: &[u32], k_max: u32) -> (MMCQ, Vec<SplitEvent>) {
+        // same as 'from_pixels_u32_rgba', along with every split median cut made, in order
+        let mut trace = Vec::new();
+        let m = MMCQ::from_pixels_with(pixels, &MmcqBuilder::new().max_colors(k_max), None, Some(&mut trace)).expect("quantization cannot be canceled without a cancel flag");
+        (m, trace)
+    }
+
+    pub fn from_pixels_u32_rgba_with_alpha(pixels: &[u32], k_max: u32, alpha_mode: AlphaMode) -> MMCQ {
+        MmcqBuilder::new().max_colors(k_max).alpha_mode(alpha_mode).build_from_u32(pixels)
+    }
+
+    pub fn from_pixels_max_palette_bytes(pixels: &[u32], max_bytes: usize) -> MMCQ {
+        // every palette entry takes 3 bytes (red, green, blue)
+        let k_max = ::core::cmp::min(max_bytes / 3, u32::MAX as usize) as u32;
+        if k_max == 0 {
+            // not even one entry fits, the palette is left empty
+            return MMCQ::with_palette(Vec::new());
+        }
+        MMCQ::from_pixels_u32_rgba(pixels, k_max)
+    }
+
+    pub fn from_pixels_target_total_bytes(pixels: &[u32], pixel_count: usize, max_total_bytes: usize) -> MMCQ {
+        // the largest k whose palette (3 bytes per entry) and index plane (ceil(log2 k) bits
+        // per pixel) fit the budget together, every bit width is tried with the most entries it can address
+        let mut k_max = 0;
+        for bits in 0..32u32 {
+            let index_bytes = (pixel_count as u64 * bits as u64).div_ceil(8);
+            if index_bytes > max_total_bytes as u64 {
+                break;
+            }
+            let fitting = ((max_total_bytes as u64 - index_bytes) / 3).min(1 << bits);
+            let fewest = if bits == 0 { 1 } else { (1u64 << (bits - 1)) + 1 };
+            if fitting >= fewest {
+                k_max = fitting as u32;
+            }
+        }
+        if k_max == 0 {
+            // not even a single entry fits, the palette is left empty
+            return MMCQ::with_palette(Vec::new());
+        }
+        MMCQ::from_pixels_u32_rgba(pixels, k_max)
+    }
+
+    pub fn from_pixels_cancellable(pixels: &[u32], k_max: u32, cancel: &AtomicBool) -> Option<MMCQ> {
+        MMCQ::from_pixels_with(pixels, &MmcqBuilder::new().max_colors(k_max), Some(cancel), None)
+    }
+
+    pub fn from_pixels_bilateral(pixels: &[u32], width: usize, height: usize, k_max: u32, sigma_space: f32, sigma_color: f32) -> MMCQ {
+        // the noise is smoothed out before the histogram is built, while the edges are kept
+        let filtered = bilateral_filter(pixels, width, height, sigma_space, sigma_color);
+        MMCQ::from_pixels_u32_rgba(&filtered, k_max)
+    }
+
+    pub fn from_pixels_downscaled(pixels: &[u32], width: usize, height: usize, k_max: u32, max_dim: usize) -> MMCQ {
+        // the histogram is built from a thumbnail at most max_dim pixels on the longer side,
+        // whose pixels are the mean colors of the image blocks so that the color proportions hold;
+        // an accent smaller than a block is mixed with its surroundings and may not make it into
+        // the palette, see 'box_downscale'
+        let thumbnail = box_downscale(pixels, width, height, max_dim);
+        MMCQ::from_pixels_u32_rgba(&thumbnail, k_max)
+    }
+
+    pub fn from_pixels_bitdepths(pixels: &[u32], k_max: u32, r_bits: u8, g_bits: u8, b_bits: u8) -> MMCQ {
+        // the image is reduced to the channel depths before the median cut, and the averaged
+        // representatives are snapped back onto them, so every palette color is representable
+        let reduced: Vec<u32> = pixels.iter()
+            .map(|&rgb| {
+                let (red, grn, blu) = ColorNode::unpack_rgb(rgb);
+                ColorNode::pack_rgb(reduce_channel_depth(red, r_bits), reduce_channel_depth(grn, g_bits), reduce_channel_depth(blu, b_bits))
+            })
+            .collect();
+
+        let mut m = MMCQ::from_pixels_u32_rgba(&reduced, k_max);
+        for color in &mut m.quant_colors {
+            *color = ColorNode::new_colors(reduce_channel_depth(color.red, r_bits), reduce_channel_depth(color.grn, g_bits), reduce_channel_depth(color.blu, b_bits), color.cnt);
+        }
+        m.update_palette_coords();
+        m
+    }
+
+    pub fn from_pixels_region_budget(pixels: &[u32], k_max: u32, regions: &[RegionBudget]) -> MMCQ {
+        // Every region is an inclusive RGB box with its own color budget, a color belongs
+        // to the first region that contains it. The colors outside of all regions share
+        // what is left of k_max. The budgets are granted in order for as long as k_max lasts,
+        // the colors of a region left without a budget count as outside of all regions,
+        // and those always keep at least one entry.
+        fn contains(color: &ColorNode, lo: (u8, u8, u8), hi: (u8, u8, u8)) -> bool {
+            lo.0 <= color.red && color.red <= hi.0 && lo.1 <= color.grn && color.grn <= hi.1 && lo.2 <= color.blu && color.blu <= hi.2
+        }
+
+        let color_hist = ColorHistogram::new_pixels(pixels);
+        let mut partitions = vec![Vec::new(); regions.len() + 1];
+        for (&rgb, &cnt) in color_hist.color_array.iter().zip(color_hist.count_array.iter()) {
+            let color = ColorNode::new_rgb(rgb, cnt);
+            let idx = regions.iter().position(|&(lo, hi, _)| contains(&color, lo, hi)).unwrap_or(regions.len());
+            partitions[idx].push(color);
+        }
+
+        let outside = regions.len();
+        let mut left = k_max.max(1);
+        let mut budgets: Vec<u32> = regions
+            .iter()
+            .map(|region| {
+                let budget = region.2.min(left);
+                left -= budget;
+                budget
+            })
+            .collect();
+        loop {
+            for i in (0..outside).filter(|&i| budgets[i] == 0) {
+                let colors = core::mem::take(&mut partitions[i]);
+                partitions[outside].extend(colors);
+            }
+            if left > 0 || partitions[outside].is_empty() {
+                break;
+            }
+            // the entry for the colors outside is taken from the last region that has any
+            let last = budgets.iter().rposition(|&b| b > 0).expect("k_max is spent on the regions");
+            budgets[last] -= 1;
+            left += 1;
+        }
+        budgets.push(left);
+        let (palette, boxes) = MMCQ::cut_partitions(&mut partitions, &budgets);
+
+        let mut m = MMCQ::with_palette(Vec::new());
+        m.image_colors = partitions.concat();
+        m.set_palette(palette, boxes);
+
+        m
+    }
+
+    pub fn from_pixels_priority_color(pixels: &[u32], k_max: u32, priority: (u8, u8, u8), extra: u32) -> MMCQ {
+        // The priority color always gets its own palette entry. The image colors close to it
+        // get 'extra' entries of their own, the rest of the image shares what is left of k_max.
+        // 'extra' is cut down to what k_max leaves besides the priority entry, and entries one
+        // side has no colors for go to the other. Colors that are left without any entries are
+        // covered by the priority entry.
+        const NEIGHBORHOOD: i32 = 48;
+
+        let (red, grn, blu) = priority;
+        let color_hist = ColorHistogram::new_pixels(pixels);
+        let mut partitions = vec![Vec::new(); 3];
+        for (&rgb, &cnt) in color_hist.color_array.iter().zip(color_hist.count_array.iter()) {
+            let color = ColorNode::new_rgb(rgb, cnt);
+            let d2 = color.distance2(red, grn, blu);
+            let idx = if d2 == 0 {
+                0
+            } else if d2 <= NEIGHBORHOOD * NEIGHBORHOOD {
+                1
+            } else {
+                2
+            };
+            partitions[idx].push(color);
+        }
+
+        let k_max = k_max.max(1);
+        let extra = extra.min(k_max - 1);
+        let rest = k_max - 1 - extra;
+        // a side with fewer colors than its budget leaves the unused entries to the other one
+        let (near, far) = (partitions[1].len() as u32, partitions[2].len() as u32);
+        let (extra, rest) = (extra + rest.saturating_sub(far), rest + extra.saturating_sub(near));
+        if extra == 0 {
+            let near = core::mem::take(&mut partitions[1]);
+            partitions[2].extend(near);
+        }
+        if rest == 0 {
+            let far = core::mem::take(&mut partitions[2]);
+            partitions[0].extend(far);
+        }
+
+        let exact = partitions[0].len();
+        let cnt = partitions[0].iter().map(|c| c.cnt).sum();
+        let budgets = [0, extra, rest];
+        let (mut palette, mut boxes) = MMCQ::cut_partitions(&mut partitions, &budgets);
+        palette.push(ColorNode::new_colors(red, grn, blu, cnt));
+        boxes.push(ColorBox::new(0, exact, 0, &partitions[0]));
+
+        let mut m = MMCQ::with_palette(Vec::new());
+        m.image_colors = partitions.concat();
+        m.set_palette(palette, boxes);
+
+        m
+    }
+
+    pub fn from_pixels_seeded(pixels: &[u32], k_max: u32, seed: &[(u8, u8, u8)]) -> MMCQ {
+        // The image colors are grouped by their closest seed color and every group starts
+        // as a box of its own, median cut then keeps splitting these boxes up to k_max.
+        // Seed colors beyond k_max are not used.
+        let seed = &seed[..seed.len().min(k_max as usize)];
+        let color_hist = ColorHistogram::new_pixels(pixels);
+        if seed.is_empty() || color_hist.color_array.len() <= k_max as usize {
+            return MMCQ::from_pixels_u32_rgba(pixels, k_max);
+        }
+
+        let mut partitions = vec![Vec::new(); seed.len()];
+        for (&rgb, &cnt) in color_hist.color_array.iter().zip(color_hist.count_array.iter()) {
+            let color = ColorNode::new_rgb(rgb, cnt);
+            let idx = (0..seed.len()).min_by_key(|&i| color.distance2(seed[i].0, seed[i].1, seed[i].2)).unwrap_or(0);
+            partitions[idx].push(color);
+        }
+
+        let mut colors = Vec::new();
+        let mut seed_boxes = Vec::new();
+        for partition in partitions.into_iter().filter(|p| !p.is_empty()) {
+            let lower = colors.len();
+            colors.extend(partition);
+            seed_boxes.push(ColorBox::new(lower, colors.len(), 0, &colors));
+        }
+
+        let options = MmcqBuilder::new().max_colors(k_max);
+        let boxes = MMCQ::split_boxes(&mut colors, seed_boxes, k_max, &options, None, None).expect("quantization cannot be canceled without a cancel flag");
+        let palette = MMCQ::average_colors(&mut colors, &boxes, options.representative, options.space);
+
+        let mut m = MMCQ::with_palette(Vec::new());
+        m.image_colors = colors;
+        m.set_palette(palette, boxes);
+        m
+    }
+
+    fn cut_partitions(partitions: &mut [Vec<ColorNode>], budgets: &[u32]) -> (Vec<ColorNode>, Vec<ColorBox>) {
+        // median cut is run over every partition with its own budget, the boxes are
+        // indexed as if the partitions were concatenated
+        let options = MmcqBuilder::new();
+        let mut palette = Vec::new();
+        let mut boxes = Vec::new();
+        let mut offset = 0;
+        for (colors, &budget) in partitions.iter_mut().zip(budgets) {
+            if budget > 0 && !colors.is_empty() {
+                let (p, b) = MMCQ::median_cut(colors, budget, &options, None, None).expect("quantization cannot be canceled without a cancel flag");
+                palette.extend(p);
+                boxes.extend(b.into_iter().map(|mut b| {
+                    b.lower += offset;
+                    b.upper += offset;
+                    b
+                }));
+            }
+            offset += colors.len();
+        }
+        (palette, boxes)
+    }
+
+    fn from_pixels_with(pixels: &[u32], options: &MmcqBuilder, cancel: Option<&AtomicBool>, trace: Option<&mut Vec<SplitEvent>>) -> Option<MMCQ> {
+        // the cancel flag is checked cooperatively, before building the histogram and between the splits
+        if cancel.is_some_and(|c| c.load(Ordering::Relaxed)) {
+            return None;
+        }
+
+        let premultiplied: Vec<u32>;
+        let pixels = if options.alpha_mode == AlphaMode::Premultiply {
+            premultiplied = premultiply_merged(pixels);
+            &premultiplied[..]
+        } else {
+            pixels
         };
 
-        m.quant_colors = m.find_representative_colors(&pixels, k_max);
-        m.quant_colors.sort_by(|a, b| b.cnt.cmp(&a.cnt));
+        let color_hist = {
+            let _span = phase_span!("histogram");
+            ColorHistogram::new_pixels(pixels)
+        };
+        MMCQ::from_histogram_with(color_hist, options, cancel, trace)
+    }
+
+    fn from_histogram_with(color_hist: ColorHistogram, options: &MmcqBuilder, cancel: Option<&AtomicBool>, trace: Option<&mut Vec<SplitEvent>>) -> Option<MMCQ> {
+        let mut m = MMCQ::with_palette(Vec::new());
+        m.space = options.space;
+        m.alpha_mode = options.alpha_mode;
+        m.metric = options.metric;
 
+        let mut cut_options = *options;
+        if options.first_is_average {
+            // one palette entry is reserved for the average color
+            cut_options.k_max = options.k_max.saturating_sub(1);
+        }
+
+        let (palette, boxes) = m.find_representative_colors(color_hist, &cut_options, cancel, trace)?;
+        m.set_palette(palette, boxes);
+
+        if options.first_is_average && !m.image_colors.is_empty() {
+            // the average color does not stand for any image colors of its own,
+            // so it gets an empty box and a zero count
+            let all = ColorBox::new(0, m.image_colors.len(), 0, &m.image_colors);
+            let average = all.get_representative_color(&mut m.image_colors, Representative::Mean, options.space);
+            m.quant_colors.insert(0, ColorNode { cnt: 0, ..average });
+            m.quant_boxes.insert(0, ColorBox::new(0, 0, 0, &m.image_colors));
+            m.update_palette_coords();
+        }
+
+        if options.neutral_anchor {
+            m.anchor_neutral();
+        }
+
+        Some(m)
+    }
+
+    fn anchor_neutral(&mut self) {
+        // if the image has near-neutral grays but the palette has no neutral entry, the entry
+        // closest to the mean gray of the image is moved onto the neutral axis there
+        fn is_neutral(c: &ColorNode) -> bool {
+            c.red.max(c.grn).max(c.blu) - c.red.min(c.grn).min(c.blu) <= NEUTRAL_TOLERANCE
+        }
+
+        let (mut sum, mut n) = (0u64, 0u64);
+        for c in self.image_colors.iter().map(|c| ColorNode::new_rgb(c.rgb, c.cnt)).filter(is_neutral) {
+            sum += c.cnt as u64 * (c.red as u64 + c.grn as u64 + c.blu as u64);
+            n += c.cnt as u64 * 3;
+        }
+        if n == 0 || self.quant_colors.iter().any(is_neutral) {
+            return;
+        }
+        let gray = ((sum + n / 2) / n) as u8;
+        if let Some(closest) = self.quant_colors.iter_mut().min_by_key(|c| c.distance2(gray, gray, gray)) {
+            *closest = ColorNode::new_colors(gray, gray, gray, closest.cnt);
+        }
+        self.update_palette_coords();
+    }
+
+    fn set_palette(&mut self, palette: Vec<ColorNode>, boxes: Vec<ColorBox>) {
+        // the palette is sorted by usage, the boxes it was built from are kept aligned with it
+        let mut entries: Vec<_> = palette.into_iter().zip(boxes).collect();
+        entries.sort_by_key(|e| core::cmp::Reverse(e.0.cnt));
+        let (palette, boxes) = entries.into_iter().unzip();
+        self.quant_colors = palette;
+        self.quant_boxes = boxes;
+        self.update_palette_coords();
+    }
+
+    fn with_palette(quant_colors: Vec<ColorNode>) -> MMCQ {
+        let mut m = MMCQ {
+            image_colors: Vec::new(),
+            quant_colors,
+            quant_boxes: Vec::new(),
+            space: DistanceSpace::Rgb,
+            quant_coords: Vec::new(),
+            quant_tree: KdTree::default(),
+            alpha_mode: AlphaMode::Ignore,
+            metric: DistanceMetric::Euclidean,
+        };
+        m.update_palette_coords();
         m
     }
 
+    pub fn from_centroids(centroids: &[(u8, u8, u8, usize)]) -> MMCQ {
+        // the centroids are taken as the palette as-is, no median cut is performed
+        MMCQ::with_palette(centroids.iter().map(|&(red, grn, blu, cnt)| ColorNode::new_colors(red, grn, blu, cnt)).collect())
+    }
+
+    pub fn from_palette_rgb(colors: &[(u8, u8, u8)]) -> MMCQ {
+        // the colors come without any pixel counts, their order is kept as given
+        MMCQ::with_palette(colors.iter().map(|&(red, grn, blu)| ColorNode::new_colors(red, grn, blu, 0)).collect())
+    }
+
+    pub fn from_gpl(text: &str) -> Result<MMCQ, ParseError> {
+        let colors = gpl::parse(text)?;
+        Ok(MMCQ::from_palette_rgb(&colors))
+    }
+
+    pub fn from_palette(palette: Palette) -> MMCQ {
+        MMCQ::with_palette(palette.0)
+    }
+
+    pub fn from_ase(bytes: &[u8]) -> Result<MMCQ, AseError> {
+        let colors = ase::parse(bytes)?;
+        Ok(MMCQ::from_palette_rgb(&colors))
+    }
+
+    pub fn quantize_atlas(tiles: &[&[u32]], colors_per_tile: u32) -> (Vec<(u8, u8, u8)>, Vec<AtlasTile>) {
+        // every tile gets a local palette, the local palettes are packed into a global one
+        // and each tile's local indices are relative to its offset in the global palette
+        assert!(colors_per_tile <= 256, "tile palettes do not fit into u8 indices");
+        let mut palette = Vec::new();
+        let mut quant_tiles = Vec::with_capacity(tiles.len());
+        for tile in tiles {
+            let m = MMCQ::from_pixels_u32_rgba(tile, colors_per_tile);
+            let indices = tile.iter().map(|&rgb| m.find_closest_color_index(rgb) as u8).collect();
+            quant_tiles.push((palette.len(), indices));
+            palette.extend(m.palette_rgb());
+        }
+        (palette, quant_tiles)
+    }
+
     pub fn get_quantized_colors(&self) -> &Vec<ColorNode> {
         &self.quant_colors
     }
 
+    pub fn to_palette(&self) -> Palette {
+        // a copy of the palette colors, see 'from_palette'
+        Palette(self.quant_colors.clone())
+    }
+
+    pub fn palette_rgb(&self) -> Vec<(u8, u8, u8)> {
+        // palette colors in the order of 'get_quantized_colors', most used first; an average
+        // color put in front by 'first_is_average' stands for no pixels and stays in front
+        self.quant_colors.iter().map(|c| (c.red, c.grn, c.blu)).collect()
+    }
+
+    pub fn palette_flat_rgb(&self) -> Vec<u8> {
+        // palette colors as consecutive r, g, b bytes, e.g. for a 'Uint8Array' on the JS side
+        self.quant_colors.iter().flat_map(|c| [c.red, c.grn, c.blu]).collect()
+    }
+
+    pub fn palette_with_counts(&self) -> Vec<((u8, u8, u8), usize)> {
+        // palette colors with the number of image pixels each of them stands for, most used first
+        // apart from an average color put in front by 'first_is_average', its count is zero
+        self.quant_colors.iter().map(|c| ((c.red, c.grn, c.blu), c.cnt)).collect()
+    }
+
+    pub fn dominant_color(&self) -> Option<(u8, u8, u8)> {
+        // the palette color standing for the most pixels, none for an empty palette
+        self.dominant_colors(1).into_iter().next()
+    }
+
+    pub fn dominant_colors(&self, n: usize) -> Vec<(u8, u8, u8)> {
+        // up to n palette colors, most used first; sorted again as an average color
+        // put in front by 'first_is_average' stands for no pixels
+        let mut colors: Vec<&ColorNode> = self.quant_colors.iter().collect();
+        colors.sort_by_key(|c| core::cmp::Reverse(c.cnt));
+        colors.into_iter().take(n).map(|c| (c.red, c.grn, c.blu)).collect()
+    }
+
+    pub fn palette_hex(&self) -> Vec<String> {
+        self.quant_colors.iter().map(|c| c.to_hex()).collect()
+    }
+
+    pub fn palette_rgba(&self, alpha: u8) -> Vec<(u8, u8, u8, u8)> {
+        self.quant_colors.iter().map(|c| (c.red, c.grn, c.blu, alpha)).collect()
+    }
+
+    pub fn color_regions(&self) -> Vec<ColorRegion> {
+        // boxes in the order of the palette, empty for the quantizers made from a palette alone
+        self.quant_boxes
+            .iter()
+            .map(|b| ColorRegion {
+                rmin: b.rmin,
+                rmax: b.rmax,
+                gmin: b.gmin,
+                gmax: b.gmax,
+                bmin: b.bmin,
+                bmax: b.bmax,
+                count: b.count,
+                level: b.level,
+            })
+            .collect()
+    }
+
+    pub fn palette_levels(&self) -> Vec<isize> {
+        // split level of the box of every palette color, 0 is the whole image and every split
+        // adds one; palettes not built from an image are all on level 0
+        if self.quant_boxes.len() != self.quant_colors.len() {
+            return vec![0; self.quant_colors.len()];
+        }
+        self.quant_boxes.iter().map(|b| b.level).collect()
+    }
+
+    pub fn palette_at_level(&self, max_level: isize) -> Vec<(u8, u8, u8)> {
+        // the coarser palette of the boxes split no deeper than max_level, most used first.
+        // Level 0 is always the single count weighted mean of the image, the halves of the deeper
+        // splits are merged into the count weighted mean of the image colors they cover.
+        // Palettes not built from an image have no splits, their deeper levels are returned as they are
+        if max_level <= 0 {
+            let colors = if self.image_colors.is_empty() { &self.quant_colors } else { &self.image_colors };
+            return MMCQ::mean_color(colors).into_iter().collect();
+        }
+        if self.quant_boxes.len() != self.quant_colors.len() {
+            return self.palette_rgb();
+        }
+        let mut entries: Vec<(usize, &ColorBox)> = self.quant_boxes.iter().enumerate().collect();
+        entries.sort_by_key(|(_, b)| (b.lower, b.upper));
+
+        // ordered by their ranges the boxes are the leaves of the split trees from left to right,
+        // so two neighbors on the same level are the halves of one split and their parent covers
+        // both ranges; every tree is rooted on level 0, which is never merged here
+        let mut merged: Vec<(isize, usize, usize, usize, Option<usize>)> = Vec::new();
+        for (i, b) in entries {
+            merged.push((b.level, b.lower, b.upper, self.quant_colors[i].cnt, Some(i)));
+            while merged.len() >= 2 {
+                let (upper, lower) = (merged[merged.len() - 1], merged[merged.len() - 2]);
+                if upper.0 != lower.0 || upper.0 <= max_level {
+                    break;
+                }
+                merged.truncate(merged.len() - 2);
+                merged.push((lower.0 - 1, lower.1, upper.2, lower.3 + upper.3, None));
+            }
+        }
+
+        // the same counts keep the order of the palette
+        merged.sort_by_key(|e| (core::cmp::Reverse(e.3), e.4.unwrap_or(usize::MAX)));
+        merged
+            .iter()
+            .filter_map(|&(_, lower, upper, _, leaf)| match leaf {
+                Some(i) => Some((self.quant_colors[i].red, self.quant_colors[i].grn, self.quant_colors[i].blu)),
+                None => MMCQ::mean_color(&self.image_colors[lower..upper]),
+            })
+            .collect()
+    }
+
+    fn mean_color(colors: &[ColorNode]) -> Option<(u8, u8, u8)> {
+        // count weighted mean of the colors, taken from their packed RGB so that the working
+        // channels of the image colors do not matter; colors without counts weigh the same
+        let total: u64 = colors.iter().map(|c| c.cnt as u64).sum();
+        let weight = |c: &ColorNode| if total > 0 { c.cnt as u64 } else { 1 };
+        let n = if total > 0 { total } else { colors.len() as u64 };
+        if n == 0 {
+            return None;
+        }
+        let mut sum = [0u64; 3];
+        for c in colors {
+            let (red, grn, blu) = ColorNode::unpack_rgb(c.rgb);
+            sum[0] += red as u64 * weight(c);
+            sum[1] += grn as u64 * weight(c);
+            sum[2] += blu as u64 * weight(c);
+        }
+        let mean = |s: u64| ((s + n / 2) / n) as u8;
+        Some((mean(sum[0]), mean(sum[1]), mean(sum[2])))
+    }
+
+    pub fn cluster_spreads(&self) -> Vec<[f64; 3]> {
+        // per-channel standard deviation of the image colors within each palette box; palettes
+        // without boxes, e.g. after 'reduce_palette', group the image colors by their closest
+        // palette color instead, and without an image every spread is zero
+        if self.quant_boxes.len() == self.quant_colors.len() {
+            return self.quant_boxes.iter().map(|b| b.get_spread(&self.image_colors)).collect();
+        }
+        let mut clusters = vec![Vec::new(); self.quant_colors.len()];
+        for c in &self.image_colors {
+            clusters[self.find_closest_color_index(c.rgb & 0xFFFFFF)].push(*c);
+        }
+        clusters.iter().map(|colors| ColorBox::new(0, colors.len(), 0, colors).get_spread(colors)).collect()
+    }
+
+    pub fn build_codec(&self) -> Codec {
+        // the codec keeps a palette-only copy of the quantizer, so that it encodes exactly
+        // like 'quantize_image'
+        Codec {
+            palette: self.quant_colors.iter().map(|c| c.rgb).collect(),
+            quantizer: MMCQ {
+                image_colors: Vec::new(),
+                quant_colors: self.quant_colors.clone(),
+                quant_boxes: Vec::new(),
+                space: self.space,
+                quant_coords: self.quant_coords.clone(),
+                quant_tree: self.quant_tree.clone(),
+                alpha_mode: self.alpha_mode,
+                metric: self.metric,
+            },
+        }
+    }
+
+    pub fn chroma_points(&self) -> Vec<(f32, f32)> {
+        // (Cb, Cr) of every palette color, full range BT.601, neutral colors are at (128, 128)
+        self.quant_colors
+            .iter()
+            .map(|c| {
+                let (r, g, b) = (c.red as f32, c.grn as f32, c.blu as f32);
+                let cb = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+                let cr = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+                (cb, cr)
+            })
+            .collect()
+    }
+
+    pub fn channel_steps(&self) -> (f64, f64, f64) {
+        // average spacing between the distinct palette values of each channel
+        let step = |channel: fn(&ColorNode) -> u8| {
+            let mut values: Vec<u8> = self.quant_colors.iter().map(channel).collect();
+            values.sort_unstable();
+            values.dedup();
+            if values.len() < 2 {
+                0.0
+            } else {
+                (values[values.len() - 1] - values[0]) as f64 / (values.len() - 1) as f64
+            }
+        };
+        (step(|c| c.red), step(|c| c.grn), step(|c| c.blu))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.quant_colors.is_empty() {
+            return Err("palette is empty".to_string());
+        }
+        for (i, color) in self.quant_colors.iter().enumerate() {
+            if color.rgb > 0xFFFFFF {
+                return Err(format!("palette color {} is out of range: {:#x}", i, color.rgb));
+            }
+        }
+
+        // palettes that were not built from an image have no boxes to check, the ones that
+        // dropped their boxes still have to account for every pixel of the image
+        if self.quant_boxes.is_empty() {
+            let total: usize = self.image_colors.iter().map(|c| c.cnt).sum();
+            let counted: usize = self.quant_colors.iter().map(|c| c.cnt).sum();
+            if !self.image_colors.is_empty() && counted != total {
+                return Err(format!("palette counts {} pixels, but the image has {}", counted, total));
+            }
+            return Ok(());
+        }
+        if self.quant_boxes.len() != self.quant_colors.len() {
+            return Err(format!("{} boxes for {} palette colors", self.quant_boxes.len(), self.quant_colors.len()));
+        }
+        for (i, b) in self.quant_boxes.iter().enumerate() {
+            if b.lower > b.upper || b.upper > self.image_colors.len() {
+                return Err(format!("box {} has invalid bounds {}..{} for {} image colors", i, b.lower, b.upper, self.image_colors.len()));
+            }
+        }
+        let total: usize = self.image_colors.iter().map(|c| c.cnt).sum();
+        let boxed: usize = self.quant_boxes.iter().map(|b| b.count).sum();
+        if boxed != total {
+            return Err(format!("boxes count {} pixels, but the image has {}", boxed, total));
+        }
+        Ok(())
+    }
+
+    pub fn color_reduction(&self) -> (usize, usize) {
+        // (unique colors of the source image, colors in the palette)
+        (self.image_colors.len(), self.quant_colors.len())
+    }
+
+    pub fn unique_color_count(&self) -> usize {
+        // distinct RGB colors of the source image, alpha ignored
+        self.image_colors.len()
+    }
+
     pub fn quantize_image(&mut self, orig_pixels: &Vec<u32>) -> Vec<u32> {
         let mut quant_pixels = orig_pixels.clone();
-        for i in 0..orig_pixels.len() {
-            let color = self.find_closest_color(orig_pixels[i]);
-            quant_pixels[i] = color.rgb;
+        self.quantize_in_place(&mut quant_pixels);
+        quant_pixels
+    }
+
+    pub fn quantize_in_place(&self, pixels: &mut [u32]) {
+        if self.quant_colors.is_empty() {
+            // an empty palette has no colors to map to, the pixels are left as they are
+            return;
+        }
+
+        // the closest color of every distinct RGB is searched only once
+        let mut closest: ColorMap<u32, u32> = ColorMap::default();
+        for px in pixels.iter_mut() {
+            let rgb = self.prepare_pixel(*px) & 0xFFFFFF;
+            let quant = *closest.entry(rgb).or_insert_with(|| self.find_closest_color(rgb).rgb);
+            *px = self.restore_alpha(quant, *px);
+        }
+    }
+
+    pub fn quantize_iter<'a>(&'a self, pixels: &'a [u32]) -> impl Iterator<Item = u32> + 'a {
+        // same as 'quantize_image', with every pixel mapped only when it is pulled
+        pixels.iter().map(move |&rgba| self.quantize_pixel(rgba))
+    }
+
+    #[cfg(feature = "rayon")]
+    pub fn quantize_image_par(&self, orig_pixels: &[u32]) -> Vec<u32> {
+        use rayon::prelude::*;
+
+        // same as 'quantize_image', with the pixels mapped in parallel
+        orig_pixels.par_iter().map(|&rgba| self.quantize_pixel(rgba)).collect()
+    }
+
+    fn quantize_pixel(&self, rgba: u32) -> u32 {
+        if self.quant_colors.is_empty() {
+            return rgba;
+        }
+        self.restore_alpha(self.find_closest_color(self.prepare_pixel(rgba)).rgb, rgba)
+    }
+
+    fn quantize_pixel_index(&self, rgba: u32) -> usize {
+        self.find_closest_color_index(self.prepare_pixel(rgba))
+    }
+
+    pub fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn restore_alpha(&self, quant: u32, orig: u32) -> u32 {
+        match self.alpha_mode {
+            AlphaMode::Ignore => quant,
+            AlphaMode::Preserve | AlphaMode::Premultiply => quant | (orig & 0xFF000000),
+        }
+    }
+
+    fn prepare_pixel(&self, rgba: u32) -> u32 {
+        // the pixel as the palette sees it
+        if self.alpha_mode == AlphaMode::Premultiply {
+            premultiply(rgba)
+        } else {
+            rgba
+        }
+    }
+
+    pub fn stretch_palette(&mut self) {
+        // linearly maps the range of every channel used by the palette onto 0..255,
+        // channels with a single value are left as they are
+        fn stretch(value: u8, min: u8, max: u8) -> u8 {
+            if max > min {
+                ((value - min) as u32 * 255 / (max - min) as u32) as u8
+            } else {
+                value
+            }
+        }
+
+        if self.quant_colors.is_empty() {
+            return;
+        }
+        let (mut rmin, mut gmin, mut bmin) = (255u8, 255u8, 255u8);
+        let (mut rmax, mut gmax, mut bmax) = (0u8, 0u8, 0u8);
+        for color in &self.quant_colors {
+            rmin = rmin.min(color.red);
+            rmax = rmax.max(color.red);
+            gmin = gmin.min(color.grn);
+            gmax = gmax.max(color.grn);
+            bmin = bmin.min(color.blu);
+            bmax = bmax.max(color.blu);
+        }
+        for color in &mut self.quant_colors {
+            *color = ColorNode::new_colors(stretch(color.red, rmin, rmax), stretch(color.grn, gmin, gmax), stretch(color.blu, bmin, bmax), color.cnt);
+        }
+        self.update_palette_coords();
+    }
+
+    pub fn reduce_palette(&mut self, target: usize) {
+        // merges the two closest palette colors into their count weighted mean until no more
+        // than target colors are left (at least one), most used first afterwards, so an average
+        // color put in front by 'first_is_average' is sorted in with the others; the merged
+        // colors no longer stand for single boxes, so the boxes are dropped
+        let target = target.max(1);
+        if self.quant_colors.len() <= target {
+            return;
+        }
+        while self.quant_colors.len() > target {
+            let mut closest = (i32::MAX, 0, 0);
+            for i in 0..self.quant_colors.len() {
+                let a = self.quant_colors[i];
+                for j in i + 1..self.quant_colors.len() {
+                    let d2 = a.distance2(self.quant_colors[j].red, self.quant_colors[j].grn, self.quant_colors[j].blu);
+                    if d2 < closest.0 {
+                        closest = (d2, i, j);
+                    }
+                }
+            }
+
+            let (_, i, j) = closest;
+            let (a, b) = (self.quant_colors[i], self.quant_colors[j]);
+            // colors standing for no pixels are weighted alike
+            let (wa, wb) = if a.cnt + b.cnt == 0 { (1, 1) } else { (a.cnt as u64, b.cnt as u64) };
+            let mean = |x: u8, y: u8| ((x as u64 * wa + y as u64 * wb + (wa + wb) / 2) / (wa + wb)) as u8;
+            self.quant_colors[i] = ColorNode::new_colors(mean(a.red, b.red), mean(a.grn, b.grn), mean(a.blu, b.blu), a.cnt + b.cnt);
+            self.quant_colors.remove(j);
+        }
+        self.quant_colors.sort_by_key(|c| core::cmp::Reverse(c.cnt));
+        self.quant_boxes = Vec::new();
+        self.update_palette_coords();
+    }
+
+    pub fn map_palette<F: FnMut((u8, u8, u8)) -> (u8, u8, u8)>(&mut self, mut f: F) {
+        // transforms every palette color in place, the remapping uses the transformed colors
+        for color in &mut self.quant_colors {
+            let (red, grn, blu) = f((color.red, color.grn, color.blu));
+            *color = ColorNode::new_colors(red, grn, blu, color.cnt);
+        }
+        self.update_palette_coords();
+    }
+
+    pub fn palette_as_parent_indices(&self, parent: &[(u8, u8, u8)]) -> Vec<usize> {
+        assert!(!parent.is_empty(), "parent palette is empty");
+        self.quant_colors
+            .iter()
+            .map(|color| {
+                let mut min_idx = 0;
+                let mut min_distance = i32::MAX;
+                for (i, &(red, grn, blu)) in parent.iter().enumerate() {
+                    let d2 = color.distance2(red, grn, blu);
+                    if d2 < min_distance {
+                        min_distance = d2;
+                        min_idx = i;
+                    }
+                }
+                min_idx
+            })
+            .collect()
+    }
+
+    pub fn align_to(&self, reference: &[(u8, u8, u8)]) -> Vec<usize> {
+        // Greedy assignment: the closest (palette, reference) pairs are matched first.
+        // Palette colors left without a reference color get the unused indices in order.
+        let mut pairs = Vec::with_capacity(self.quant_colors.len() * reference.len());
+        for (i, color) in self.quant_colors.iter().enumerate() {
+            for (j, &(red, grn, blu)) in reference.iter().enumerate() {
+                pairs.push((color.distance2(red, grn, blu), i, j));
+            }
+        }
+        pairs.sort();
+
+        let n = self.quant_colors.len().max(reference.len());
+        let mut mapping = vec![None; self.quant_colors.len()];
+        let mut used = vec![false; n];
+        for (_, i, j) in pairs {
+            if mapping[i].is_none() && !used[j] {
+                mapping[i] = Some(j);
+                used[j] = true;
+            }
+        }
+        let mut unused = (0..n).filter(|&j| !used[j]);
+        mapping.into_iter().map(|j| j.or_else(|| unused.next()).expect("there are as many indices as palette colors")).collect()
+    }
+
+    pub fn quantize_image_dithered(&self, pixels: &[u32], width: u32, height: u32) -> Vec<u32> {
+        // Floyd-Steinberg error diffusion, the error of every pixel is carried over
+        // to its right and lower neighbours before they are mapped
+        let (width, height) = (width as usize, height as usize);
+        assert_eq!(pixels.len(), width * height, "pixel count does not match the image size");
+        let mut errors = vec![[0f32; 3]; pixels.len()];
+        let mut quant_pixels = Vec::with_capacity(pixels.len());
+        for i in 0..pixels.len() {
+            let err = errors[i];
+            let (red, grn, blu) = ColorNode::unpack_rgb(self.prepare_pixel(pixels[i]));
+            let red = clamp_channel(red as f32 + err[0]);
+            let grn = clamp_channel(grn as f32 + err[1]);
+            let blu = clamp_channel(blu as f32 + err[2]);
+            let color = self.find_closest_color(ColorNode::pack_rgb(red, grn, blu));
+            quant_pixels.push(self.restore_alpha(color.rgb, pixels[i]));
+
+            let diff = [red as f32 - color.red as f32, grn as f32 - color.grn as f32, blu as f32 - color.blu as f32];
+            diffuse_floyd_steinberg(&mut errors, i, width, diff, |_| true);
         }
         quant_pixels
     }
 
-    fn find_representative_colors(&mut self, pixels: &[u32], k_max: u32) -> Vec<ColorNode> {
-        let color_hist = ColorHistogram::new_pixels(pixels);
+    pub fn quantize_image_ordered(&self, pixels: &[u32], width: u32, height: u32, matrix: DitherMatrix) -> Vec<u32> {
+        // Every pixel is offset by the threshold of its position in the tiled matrix, so the
+        // result only depends on the pixel and its coordinates. The offsets span the distance
+        // between the palette levels of a channel, as if the palette were a uniform grid.
+        let (width, height) = (width as usize, height as usize);
+        assert_eq!(pixels.len(), width * height, "pixel count does not match the image size");
+        let levels = (self.quant_colors.len() as f32).cbrt().round().max(1.0);
+        let spread = 255.0 / levels;
+        pixels
+            .iter()
+            .enumerate()
+            .map(|(i, &rgba)| {
+                let offset = matrix.threshold(i % width, i / width) * spread;
+                let (red, grn, blu) = ColorNode::unpack_rgb(self.prepare_pixel(rgba));
+                let dithered = ColorNode::pack_rgb(clamp_channel(red as f32 + offset), clamp_channel(grn as f32 + offset), clamp_channel(blu as f32 + offset));
+                self.restore_alpha(self.find_closest_color(dithered).rgb, rgba)
+            })
+            .collect()
+    }
+
+    pub fn quantize_image_dithered_masked(&self, pixels: &[u32], width: usize, mask: &[bool]) -> Result<Vec<u32>, MmcqError> {
+        // Floyd-Steinberg error diffusion applied only to the pixels where the mask is set,
+        // the error is never pushed over to the pixels outside of the mask
+        assert_eq!(pixels.len(), mask.len());
+        check_rows(pixels.len(), width)?;
+        let mut errors = vec![[0f32; 3]; pixels.len()];
+        let mut quant_pixels = Vec::with_capacity(pixels.len());
+        for i in 0..pixels.len() {
+            if !mask[i] {
+                quant_pixels.push(self.quantize_pixel(pixels[i]));
+                continue;
+            }
+
+            let rgb = self.prepare_pixel(pixels[i]);
+            let err = errors[i];
+            let (red, grn, blu) = ColorNode::unpack_rgb(rgb);
+            let red = clamp_channel(red as f32 + err[0]);
+            let grn = clamp_channel(grn as f32 + err[1]);
+            let blu = clamp_channel(blu as f32 + err[2]);
+            let color = self.find_closest_color(ColorNode::pack_rgb(red, grn, blu));
+            quant_pixels.push(self.restore_alpha(color.rgb, pixels[i]));
+
+            let diff = [red as f32 - color.red as f32, grn as f32 - color.grn as f32, blu as f32 - color.blu as f32];
+            diffuse_floyd_steinberg(&mut errors, i, width, diff, |j| mask[j]);
+        }
+        Ok(quant_pixels)
+    }
+
+    pub fn quantize_image_adaptive_dither(&self, pixels: &[u32], width: usize) -> Result<Vec<u32>, MmcqError> {
+        // Floyd-Steinberg error diffusion scaled by how far every pixel is from its closest
+        // palette color, pixels the palette represents exactly are neither dithered nor diffuse
+        check_rows(pixels.len(), width)?;
+        let mut errors = vec![[0f32; 3]; pixels.len()];
+        let mut quant_pixels = Vec::with_capacity(pixels.len());
+        for i in 0..pixels.len() {
+            let rgb = self.prepare_pixel(pixels[i]);
+            let distance = (self.find_closest_color(rgb).distance2_packed(rgb) as f32).sqrt();
+            let strength = (distance / ADAPTIVE_DITHER_FULL_DISTANCE).min(1.0);
+
+            let err = errors[i];
+            let (red, grn, blu) = ColorNode::unpack_rgb(rgb);
+            let red = clamp_channel(red as f32 + err[0] * strength);
+            let grn = clamp_channel(grn as f32 + err[1] * strength);
+            let blu = clamp_channel(blu as f32 + err[2] * strength);
+            let color = self.find_closest_color(ColorNode::pack_rgb(red, grn, blu));
+            quant_pixels.push(self.restore_alpha(color.rgb, pixels[i]));
+
+            let diff = [(red as f32 - color.red as f32) * strength, (grn as f32 - color.grn as f32) * strength, (blu as f32 - color.blu as f32) * strength];
+            diffuse_floyd_steinberg(&mut errors, i, width, diff, |_| true);
+        }
+        Ok(quant_pixels)
+    }
+
+    #[cfg(feature = "rayon")]
+    pub fn quantize_batch_parallel(&self, images: &[&[u32]]) -> Vec<Vec<u32>> {
+        use rayon::prelude::*;
+
+        // the palette is shared read-only, so every pixel of every image is mapped independently
+        images.par_iter().map(|pixels| pixels.par_iter().map(|&rgba| self.quantize_pixel(rgba)).collect()).collect()
+    }
+
+    pub fn quantize_indices_dirty(&self, prev_indices: &[u8], pixels: &[u32], width: usize) -> Result<(Vec<u8>, Option<Rect>), MmcqError> {
+        // returns the palette indices of the frame and the bounding rectangle of the indices
+        // that differ from the previous frame, missing previous indices count as changed
+        check_rows(pixels.len(), width)?;
+        assert!(self.quant_colors.len() <= 256, "palette does not fit into u8 indices");
+        let mut indices = Vec::with_capacity(pixels.len());
+        let mut dirty: Option<(usize, usize, usize, usize)> = None;
+        for (i, &rgb) in pixels.iter().enumerate() {
+            let idx = self.quantize_pixel_index(rgb) as u8;
+            if prev_indices.get(i) != Some(&idx) {
+                let (x, y) = (i % width, i / width);
+                dirty = Some(match dirty {
+                    Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                    None => (x, y, x, y),
+                });
+            }
+            indices.push(idx);
+        }
+        let rect = dirty.map(|(x0, y0, x1, y1)| Rect {
+            x: x0,
+            y: y0,
+            width: x1 - x0 + 1,
+            height: y1 - y0 + 1,
+        });
+        Ok((indices, rect))
+    }
+
+    pub fn quantization_error(&self, orig_pixels: &[u32]) -> f64 {
+        // sum of the squared RGB distances between the pixels and their palette colors
+        orig_pixels
+            .iter()
+            .map(|&rgba| {
+                let rgb = self.prepare_pixel(rgba);
+                self.find_closest_color(rgb).distance2_packed(rgb) as f64
+            })
+            .sum()
+    }
+
+    pub fn mean_quantization_error(&self, orig_pixels: &[u32]) -> f64 {
+        if orig_pixels.is_empty() {
+            return 0.0;
+        }
+        self.quantization_error(orig_pixels) / orig_pixels.len() as f64
+    }
+
+    pub fn ssim(&self, pixels: &[u32], width: usize, height: usize) -> f64 {
+        // mean luminance SSIM between the image and its quantized version,
+        // over 8x8 windows moved by 4 pixels (or a single window for smaller images)
+        const WINDOW: usize = 8;
+        const STEP: usize = 4;
+        const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+        const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+        assert_eq!(pixels.len(), width * height);
+        let orig: Vec<f64> = pixels.iter().map(|&rgb| luminance(rgb)).collect();
+        let quant: Vec<f64> = pixels.iter().map(|&rgba| luminance(self.quantize_pixel(rgba))).collect();
+
+        let win_w = WINDOW.min(width);
+        let win_h = WINDOW.min(height);
+        let mut total = 0.0;
+        let mut windows = 0;
+        for y0 in (0..=height.saturating_sub(win_h)).step_by(STEP) {
+            for x0 in (0..=width.saturating_sub(win_w)).step_by(STEP) {
+                let n = (win_w * win_h) as f64;
+                let (mut sum_x, mut sum_y, mut sum_xx, mut sum_yy, mut sum_xy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+                for y in y0..y0 + win_h {
+                    for x in x0..x0 + win_w {
+                        let a = orig[y * width + x];
+                        let b = quant[y * width + x];
+                        sum_x += a;
+                        sum_y += b;
+                        sum_xx += a * a;
+                        sum_yy += b * b;
+                        sum_xy += a * b;
+                    }
+                }
+                let mean_x = sum_x / n;
+                let mean_y = sum_y / n;
+                let var_x = sum_xx / n - mean_x * mean_x;
+                let var_y = sum_yy / n - mean_y * mean_y;
+                let cov = sum_xy / n - mean_x * mean_y;
+                total += ((2.0 * mean_x * mean_y + C1) * (2.0 * cov + C2)) / ((mean_x * mean_x + mean_y * mean_y + C1) * (var_x + var_y + C2));
+                windows += 1;
+            }
+        }
+        if windows == 0 {
+            1.0
+        } else {
+            total / windows as f64
+        }
+    }
+
+    pub fn to_bmp_indexed(&self, pixels: &[u32], width: u32, height: u32) -> Result<Vec<u8>, MmcqError> {
+        // fails if the padded rows do not fit into the 32-bit sizes of the file
+        let indices: Vec<u8> = pixels.iter().map(|&rgba| self.quantize_pixel_index(rgba) as u8).collect();
+        let palette = self.palette_rgb();
+        bmp::encode_indexed(&indices, &palette, width, height)
+    }
+
+    pub fn quantize_to_indices(&self, orig_pixels: &[u32]) -> (Vec<u8>, Vec<(u8, u8, u8)>) {
+        // palette index of every pixel along with the palette, for indexed image formats;
+        // panics if the palette has more than 256 colors, build it with k_max <= 256
+        assert!(self.quant_colors.len() <= 256, "palette does not fit into u8 indices");
+        let indices = orig_pixels.iter().map(|&rgba| self.quantize_pixel_index(rgba) as u8).collect();
+        (indices, self.palette_rgb())
+    }
+
+    pub fn quantize_to_indices_compact(&self, orig_pixels: &[u32]) -> (Vec<u8>, Vec<(u8, u8, u8)>) {
+        // same as 'quantize_to_indices', with the palette colors no pixel maps to dropped,
+        // the remaining colors keep their order and the indices are renumbered to match
+        let (mut indices, palette) = self.quantize_to_indices(orig_pixels);
+        let mut used = vec![false; palette.len()];
+        for &idx in &indices {
+            used[idx as usize] = true;
+        }
+
+        let mut remap = vec![0u8; palette.len()];
+        let mut compact = Vec::new();
+        for (i, color) in palette.into_iter().enumerate() {
+            if used[i] {
+                remap[i] = compact.len() as u8;
+                compact.push(color);
+            }
+        }
+        for idx in indices.iter_mut() {
+            *idx = remap[*idx as usize];
+        }
+        (indices, compact)
+    }
+
+    pub fn quantize_to_index_grid(&self, pixels: &[u32], width: usize) -> Result<Vec<Vec<u8>>, MmcqError> {
+        // palette indices as rows of the given width
+        check_rows(pixels.len(), width)?;
+        assert!(self.quant_colors.len() <= 256, "palette does not fit into u8 indices");
+        Ok(pixels.chunks(width).map(|row| row.iter().map(|&rgba| self.quantize_pixel_index(rgba) as u8).collect()).collect())
+    }
+
+    pub fn quantize_with_transparency_mask(&self, pixels: &[u32], alpha: &[u8], alpha_min: u8) -> (Vec<u8>, Vec<bool>) {
+        // palette indices of all pixels, along with a mask of the pixels whose alpha is below
+        // alpha_min, transparent pixels still get the index of their closest color
+        assert_eq!(pixels.len(), alpha.len(), "alpha plane does not match the pixels");
+        assert!(self.quant_colors.len() <= 256, "palette does not fit into u8 indices");
+        let indices = pixels.iter().map(|&rgba| self.quantize_pixel_index(rgba) as u8).collect();
+        let mask = alpha.iter().map(|&a| a < alpha_min).collect();
+        (indices, mask)
+    }
+
+    pub fn to_indexed_debug_string(&self, pixels: &[u32], width: usize) -> Result<String, MmcqError> {
+        // one line per row, a single hex digit per pixel for palettes of up to 16 colors,
+        // zero-padded space-separated hex numbers otherwise
+        check_rows(pixels.len(), width)?;
+        let digits = format!("{:x}", self.quant_colors.len().saturating_sub(1)).len();
+        let mut out = String::new();
+        for row in pixels.chunks(width) {
+            for (x, &rgb) in row.iter().enumerate() {
+                if digits > 1 && x > 0 {
+                    out.push(' ');
+                }
+                out.push_str(&format!("{:0width$x}", self.quantize_pixel_index(rgb), width = digits));
+            }
+            out.push('\n');
+        }
+        Ok(out)
+    }
+
+    pub fn redundant_for_batch(&self, images: &[&[u32]]) -> Vec<usize> {
+        // palette indices that are not the closest color of any pixel in any of the images
+        let mut used = vec![false; self.quant_colors.len()];
+        for pixels in images {
+            for &rgb in pixels.iter() {
+                used[self.quantize_pixel_index(rgb)] = true;
+            }
+        }
+        (0..used.len()).filter(|&i| !used[i]).collect()
+    }
+
+    pub fn quantize_to_blend(&self, pixels: &[u32]) -> Vec<(usize, usize, f32)> {
+        // each pixel is expressed as a blend between its two closest palette colors,
+        // t = 0 means the pixel is exactly the first one
+        pixels
+            .iter()
+            .map(|&rgba| {
+                let (idx_a, d2_a, idx_b, d2_b) = self.find_two_closest_color_indices(self.prepare_pixel(rgba));
+                let da = (d2_a as f32).sqrt();
+                let db = (d2_b as f32).sqrt();
+                let t = if da + db > 0.0 { da / (da + db) } else { 0.0 };
+                (idx_a, idx_b, t)
+            })
+            .collect()
+    }
+
+    fn find_representative_colors(&mut self, color_hist: ColorHistogram, options: &MmcqBuilder, cancel: Option<&AtomicBool>, trace: Option<&mut Vec<SplitEvent>>) -> Option<(Vec<ColorNode>, Vec<ColorBox>)> {
         let cnum = color_hist.color_array.len();
+        if cnum == 0 {
+            // nothing to quantize, the palette stays empty
+            self.image_colors = Vec::new();
+            return Some((Vec::new(), Vec::new()));
+        }
 
+        let k_max = options.k_max;
         self.image_colors = Vec::with_capacity(cnum);
         for i in 0..cnum {
             let rgb = color_hist.color_array[i];
@@ -322,32 +2335,117 @@ impl MMCQ {
 
         // println!("{:?}", self.image_colors);
 
-        let r_cols = if cnum <= k_max as usize {
-            // image has fewer colors than k_max
-            self.image_colors.clone()
+        if options.space.splits_in_space() {
+            self.image_colors = self.image_colors.iter().map(|c| options.space.to_working(c)).collect();
+        }
+
+        if let Some(power) = options.saturation_weighting {
+            // The boxes are cut with the counts scaled by the color saturation, so that the
+            // vivid colors get more of the palette. The representatives and box counts are
+            // then computed from the real pixel counts.
+            let counts: ColorMap<u32, usize> = self.image_colors.iter().map(|c| (c.rgb, c.cnt)).collect();
+            for c in &mut self.image_colors {
+                let orig = ColorNode::new_rgb(c.rgb, c.cnt);
+                let weight = c.cnt as f64 * orig.saturation().powf(power as f64) * SATURATION_WEIGHT_SCALE;
+                c.cnt = (weight.round() as usize).max(1);
+            }
+
+            let (_, mut boxes) = MMCQ::median_cut(&mut self.image_colors, k_max, options, cancel, trace)?;
+
+            for c in &mut self.image_colors {
+                c.cnt = counts[&c.rgb];
+            }
+            for b in &mut boxes {
+                b.trim(&self.image_colors);
+            }
+            let palette = MMCQ::average_colors(&mut self.image_colors, &boxes, options.representative, options.space);
+            return Some((palette, boxes));
+        }
+
+        MMCQ::median_cut(&mut self.image_colors, k_max, options, cancel, trace)
+    }
+
+    fn median_cut(colors: &mut Vec<ColorNode>, k_max: u32, options: &MmcqBuilder, cancel: Option<&AtomicBool>, trace: Option<&mut Vec<SplitEvent>>) -> Option<(Vec<ColorNode>, Vec<ColorBox>)> {
+        let cnum = colors.len();
+
+        let r_cols = if k_max == 0 {
+            // no colors left to pick, e.g. when the only entry is reserved for the average color
+            (Vec::new(), Vec::new())
+        } else if cnum <= k_max as usize && options.min_box_count <= 1 {
+            // image has fewer colors than k_max and none of them is too small to be kept,
+            // restore them from the working channels, each color is a box of its own
+            let r_cols = colors.iter().map(|c| ColorNode::new_rgb(c.rgb, c.cnt)).collect();
+            let boxes = (0..cnum).map(|i| ColorBox::new(i, i + 1, 0, colors)).collect();
+            (r_cols, boxes)
         } else {
-            let initial_box = ColorBox::new(0, cnum - 1, 0, &mut self.image_colors);
-            let mut color_set = Vec::new();
-            color_set.push(initial_box);
-            let mut k = 1;
-            let mut done = false;
-            while k < k_max && !done {
-                let new_box = if let Some(mut next_box) = self.find_box_to_split(&mut color_set) {
-                    next_box.split_box(&mut self.image_colors)
-                } else {
-                    done = true;
-                    None
-                };
-
-                if let Some(new_box) = new_box {
-                    color_set.push(new_box);
-                    k = k + 1;
+            let initial_box = ColorBox::new(0, cnum, 0, colors);
+            let color_set = MMCQ::split_boxes(colors, vec![initial_box], k_max, options, cancel, trace)?;
+            (MMCQ::average_colors(colors, &color_set, options.representative, options.space), color_set)
+        };
+        Some(r_cols)
+    }
+
+    fn split_boxes(colors: &mut Vec<ColorNode>, mut color_set: Vec<ColorBox>, k_max: u32, options: &MmcqBuilder, cancel: Option<&AtomicBool>, mut trace: Option<&mut Vec<SplitEvent>>) -> Option<Vec<ColorBox>> {
+        // splits the boxes until there are k_max of them or none can be split any further;
+        // every box of two or more colors splits, so with more than k_max colors there are
+        // exactly k_max boxes in the end
+        let _span = phase_span!("split");
+        let canceled = || cancel.is_some_and(|c| c.load(Ordering::Relaxed));
+        let mut k = color_set.len() as u32;
+        let mut done = false;
+        while k < k_max && !done {
+            if canceled() {
+                return None;
+            }
+
+            let split = if let Some(i) = MMCQ::find_box_to_split(&color_set, colors, options.box_selection) {
+                let axis = color_set[i].get_longest_color_dimension().into();
+                let new_box = color_set[i].split_box(colors, options.split_strategy, options.min_box_count);
+                if new_box.is_none() {
+                    // the box stays whole for good
+                    color_set[i].frozen = true;
                 }
+                new_box.map(|new_box| (i, axis, new_box))
+            } else if options.remainder_policy == RemainderPolicy::SubdivideLargest {
+                match MMCQ::find_largest_box(&color_set) {
+                    Some(i) => Some((i, SplitAxis::Intensity, color_set[i].subdivide_by_intensity(colors))),
+                    None => {
+                        done = true;
+                        None
+                    }
+                }
+            } else {
+                done = true;
+                None
+            };
+
+            if let Some((i, axis, new_box)) = split {
+                if let Some(trace) = trace.as_mut() {
+                    trace.push(SplitEvent {
+                        box_index: i,
+                        axis,
+                        median: color_set[i].upper,
+                        lower_count: color_set[i].count,
+                        upper_count: new_box.count,
+                    });
+                }
+                color_set.push(new_box);
+                k += 1;
             }
+        }
+        Some(color_set)
+    }
 
-            self.average_colors(&color_set)
-        };
-        r_cols
+    pub fn nearest(&self, r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+        // closest palette color by the configured distance space and metric, the palette must not be empty;
+        // the color is taken as opaque
+        let color = self.quant_colors[self.nearest_index(r, g, b)];
+        (color.red, color.grn, color.blu)
+    }
+
+    pub fn nearest_index(&self, r: u8, g: u8, b: u8) -> usize {
+        // index into 'get_quantized_colors' of the closest palette color
+        self.quantize_pixel_index(ColorNode::pack_rgb(r, g, b) | 0xFF000000)
     }
 
     fn find_closest_color(&self, rgb: u32) -> ColorNode {
@@ -356,15 +2454,31 @@ impl MMCQ {
     }
 
     fn find_closest_color_index(&self, rgb: u32) -> usize {
-        let red = ((rgb & 0xFF0000) >> 16) as u8;
-        let grn = ((rgb & 0xFF00) >> 8) as u8;
-        let blu = (rgb & 0xFF) as u8;
+        // of the equally distant palette colors the one with the lower packed RGB value wins,
+        // so the result does not depend on the palette order
+        if self.space != DistanceSpace::Rgb {
+            return self.find_closest_color_index_in_space(rgb);
+        }
+
+        if self.metric != DistanceMetric::Euclidean {
+            return self.find_closest_color_index_by_metric(rgb);
+        }
+
+        let (red, grn, blu) = ColorNode::unpack_rgb(rgb);
+        if let Some(idx) = self.quant_tree.nearest([red, grn, blu]) {
+            return idx;
+        }
+        self.find_closest_color_index_linear(rgb)
+    }
+
+    fn find_closest_color_index_linear(&self, rgb: u32) -> usize {
+        // the scan over all palette colors the kd-tree stands in for, with the same tie breaking
         let mut min_idx = 0;
-        let mut min_distance = ::std::i32::MAX;
+        let mut min_distance = i32::MAX;
         for i in 0..self.quant_colors.len() {
             let color = self.quant_colors[i];
-            let d2 = color.distance2(red, grn, blu);
-            if d2 < min_distance {
+            let d2 = color.distance2_packed(rgb);
+            if d2 < min_distance || (d2 == min_distance && color.rgb < self.quant_colors[min_idx].rgb) {
                 min_distance = d2;
                 min_idx = i;
             }
@@ -372,31 +2486,402 @@ impl MMCQ {
         min_idx
     }
 
-    fn average_colors(&mut self, color_boxes: &Vec<ColorBox>) -> Vec<ColorNode> {
+    fn find_closest_color_index_by_metric(&self, rgb: u32) -> usize {
+        let (red, grn, blu) = ColorNode::unpack_rgb(rgb);
+        let mut min_idx = 0;
+        let mut min_distance = f64::MAX;
+        for (i, color) in self.quant_colors.iter().enumerate() {
+            let d2 = self.metric.distance2(color, red, grn, blu);
+            if d2 < min_distance || (d2 == min_distance && color.rgb < self.quant_colors[min_idx].rgb) {
+                min_distance = d2;
+                min_idx = i;
+            }
+        }
+        min_idx
+    }
+
+    fn find_closest_color_index_in_space(&self, rgb: u32) -> usize {
+        let (red, grn, blu) = ColorNode::unpack_rgb(rgb);
+        let coords = self.space.to_coords(red, grn, blu);
+        let mut min_idx = 0;
+        let mut min_distance = f64::MAX;
+        for (i, qc) in self.quant_coords.iter().enumerate() {
+            let d2 = (qc[0] - coords[0]).powi(2) + (qc[1] - coords[1]).powi(2) + (qc[2] - coords[2]).powi(2);
+            if d2 < min_distance || (d2 == min_distance && self.quant_colors[i].rgb < self.quant_colors[min_idx].rgb) {
+                min_distance = d2;
+                min_idx = i;
+            }
+        }
+        min_idx
+    }
+
+    fn update_palette_coords(&mut self) {
+        // rebuilds the nearest color search structures, whenever the palette changes
+        let space = self.space;
+        if space == DistanceSpace::Rgb {
+            let colors: Vec<[u8; 3]> = self.quant_colors.iter().map(|c| [c.red, c.grn, c.blu]).collect();
+            self.quant_coords = Vec::new();
+            self.quant_tree = KdTree::new(&colors);
+        } else {
+            self.quant_coords = self.quant_colors.iter().map(|c| space.to_coords(c.red, c.grn, c.blu)).collect();
+            self.quant_tree = KdTree::default();
+        }
+    }
+
+    fn find_two_closest_color_indices(&self, rgb: u32) -> (usize, f64, usize, f64) {
+        // the two closest palette colors, measured and tie broken the same way as
+        // find_closest_color_index, so the first one is always the mapped color
+        let (red, grn, blu) = ColorNode::unpack_rgb(rgb);
+        let coords = if self.space != DistanceSpace::Rgb { Some(self.space.to_coords(red, grn, blu)) } else { None };
+        let distance2 = |i: usize| match coords {
+            Some(coords) => {
+                let qc = self.quant_coords[i];
+                (qc[0] - coords[0]).powi(2) + (qc[1] - coords[1]).powi(2) + (qc[2] - coords[2]).powi(2)
+            }
+            None if self.metric != DistanceMetric::Euclidean => self.metric.distance2(&self.quant_colors[i], red, grn, blu),
+            None => self.quant_colors[i].distance2_packed(rgb) as f64,
+        };
+        let closer = |d2: f64, i: usize, min_distance: f64, min_idx: Option<usize>| match min_idx {
+            Some(min_idx) => d2 < min_distance || (d2 == min_distance && self.quant_colors[i].rgb < self.quant_colors[min_idx].rgb),
+            None => true,
+        };
+        let (mut idx_a, mut d2_a) = (None, f64::MAX);
+        let (mut idx_b, mut d2_b) = (None, f64::MAX);
+        for i in 0..self.quant_colors.len() {
+            let d2 = distance2(i);
+            if closer(d2, i, d2_a, idx_a) {
+                idx_b = idx_a;
+                d2_b = d2_a;
+                idx_a = Some(i);
+                d2_a = d2;
+            } else if closer(d2, i, d2_b, idx_b) {
+                idx_b = Some(i);
+                d2_b = d2;
+            }
+        }
+        let idx_a = idx_a.unwrap_or(0);
+        match idx_b {
+            Some(idx_b) => (idx_a, d2_a, idx_b, d2_b),
+            // single color palette, there is nothing to blend with
+            None => (idx_a, d2_a, idx_a, d2_a),
+        }
+    }
+
+    fn average_colors(colors: &mut Vec<ColorNode>, color_boxes: &Vec<ColorBox>, representative: Representative, space: DistanceSpace) -> Vec<ColorNode> {
+        let _span = phase_span!("average");
         let n = color_boxes.len();
         let mut avg_colors = Vec::with_capacity(n);
         for b in color_boxes {
             // println!("color box {:?}", b);
-            avg_colors.push(b.get_average_color(&mut self.image_colors));
+            avg_colors.push(b.get_representative_color(colors, representative, space));
             // println!("avg {:?}", avg_colors[avg_colors.len()-1]);
         }
         return avg_colors;
     }
 
-    fn find_box_to_split<'a>(&self, color_boxes: &'a mut Vec<ColorBox>) -> Option<&'a mut ColorBox> {
+    fn find_box_to_split(color_boxes: &[ColorBox], colors: &[ColorNode], selection: BoxSelection) -> Option<usize> {
+        if selection == BoxSelection::MaxVariance {
+            // the splittable box contributing the most error
+            return color_boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| b.is_splittable())
+                .map(|(i, b)| (i, b.get_weighted_variance(colors)))
+                .fold(None, |best: Option<(usize, f64)>, (i, var)| match best {
+                    Some((_, best_var)) if best_var >= var => best,
+                    _ => Some((i, var)),
+                })
+                .map(|(i, _)| i);
+        }
+
         let mut box_to_split = None;
         // from the set of splitable color boxes
         // select the one with the minimum level
-        let mut min_level = ::std::isize::MAX;
-        for b in color_boxes {
-            if b.color_count() >= 2 {
+        let mut min_level = isize::MAX;
+        for (i, b) in color_boxes.iter().enumerate() {
+            if b.is_splittable() {
                 // box can be split
                 if b.level < min_level {
                     min_level = b.level;
-                    box_to_split = Some(b);
+                    box_to_split = Some(i);
                 }
             }
         }
         box_to_split
     }
+
+    fn find_largest_box(color_boxes: &[ColorBox]) -> Option<usize> {
+        // the box representing the most pixels that still spans more than one color
+        color_boxes.iter().enumerate().filter(|(_, b)| b.color_count() >= 2).max_by_key(|(_, b)| b.count).map(|(i, _)| i)
+    }
+}
+
+pub fn duotone(pixels: &[u32], dark: (u8, u8, u8), light: (u8, u8, u8)) -> Vec<u32> {
+    // every pixel becomes the blend of the two colors given by its luminance
+    let blend = |from: u8, to: u8, t: f64| clamp_channel((from as f64 + (to as f64 - from as f64) * t) as f32);
+    pixels.iter()
+        .map(|&rgb| {
+            let t = luminance(rgb) / 255.0;
+            ColorNode::pack_rgb(blend(dark.0, light.0, t), blend(dark.1, light.1, t), blend(dark.2, light.2, t))
+        })
+        .collect()
+}
+
+pub fn min_lossless_k(pixels: &[u32], cap: u32) -> Option<u32> {
+    // the image can be palettized losslessly if it has no more unique colors than the cap
+    let unique = count_unique_colors(pixels);
+    if unique <= cap as usize {
+        Some(unique as u32)
+    } else {
+        None
+    }
+}
+
+pub fn count_unique_colors(pixels: &[u32]) -> usize {
+    // distinct RGB colors of the pixels, alpha ignored, without building a palette
+    ColorHistogram::new_pixels(pixels).color_array.len()
+}
+
+pub fn palette_flicker(prev: &[(u8, u8, u8)], cur: &[(u8, u8, u8)]) -> f64 {
+    // average distance from every color to its best match in the other palette,
+    // taken both ways so that colors appearing or disappearing count too
+    fn best_match_mean(from: &[(u8, u8, u8)], to: &[(u8, u8, u8)]) -> f64 {
+        if from.is_empty() || to.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = from.iter()
+            .map(|&(red, grn, blu)| {
+                let color = ColorNode::new_colors(red, grn, blu, 0);
+                to.iter().map(|&(r, g, b)| color.distance2(r, g, b)).min().map_or(0.0, |d2| (d2 as f64).sqrt())
+            })
+            .sum();
+        sum / from.len() as f64
+    }
+
+    (best_match_mean(prev, cur) + best_match_mean(cur, prev)) / 2.0
+}
+
+pub struct Codec {
+    palette: Vec<u32>,
+    quantizer: MMCQ,
+}
+
+impl Codec {
+    pub fn palette(&self) -> &[u32] {
+        &self.palette
+    }
+
+    pub fn encode(&self, pixels: &[u32]) -> Vec<u8> {
+        // nearest palette index of every pixel, colors already seen are looked up instead of searched
+        assert!(self.palette.len() <= 256, "palette does not fit into u8 indices");
+        let mut seen: ColorMap<u32, u8> = ColorMap::default();
+        pixels.iter().map(|&rgb| *seen.entry(rgb).or_insert_with(|| self.quantizer.quantize_pixel_index(rgb) as u8)).collect()
+    }
+
+    pub fn decode(&self, indices: &[u8]) -> Vec<u32> {
+        // the indices carry no alpha, so the colors come back as they are in the palette;
+        // see 'decode_with_alpha' for the alpha modes that keep the alpha of the pixels
+        indices.iter().map(|&idx| self.palette[idx as usize]).collect()
+    }
+
+    pub fn decode_with_alpha(&self, indices: &[u8], alpha: &[u8]) -> Vec<u32> {
+        // same as 'decode', with the alpha of every pixel put back the way 'quantize_image' does
+        assert_eq!(indices.len(), alpha.len());
+        indices.iter().zip(alpha).map(|(&idx, &a)| self.quantizer.restore_alpha(self.palette[idx as usize], (a as u32) << 24)).collect()
+    }
+}
+
+// A color quantization algorithm: the palette it came up with and the mapping of the pixels onto it.
+pub trait Quantizer {
+    fn palette(&self) -> &[ColorNode];
+    fn quantize(&self, pixels: &[u32]) -> Vec<u32>;
+}
+
+impl Quantizer for MMCQ {
+    fn palette(&self) -> &[ColorNode] {
+        &self.quant_colors
+    }
+
+    fn quantize(&self, pixels: &[u32]) -> Vec<u32> {
+        let mut quant_pixels = pixels.to_vec();
+        self.quantize_in_place(&mut quant_pixels);
+        quant_pixels
+    }
+}
+
+// Octree quantization, see 'octree.rs'. It tends to be faster than the median cut and keeps
+// gradients smoother, the pixels are mapped onto its palette the same way as with 'MMCQ'.
+pub struct Octree {
+    quantizer: MMCQ,
+}
+
+impl Octree {
+    pub fn from_pixels_u32_rgba(pixels: &[u32], k_max: u32) -> Octree {
+        // at most k_max colors, most used first; the alpha components are ignored
+        let color_hist = ColorHistogram::new_pixels(pixels);
+        let mut palette: Vec<ColorNode> = octree::reduce(&color_hist.color_array, &color_hist.count_array, k_max.max(1) as usize).into_iter().map(|(rgb, cnt)| ColorNode::new_rgb(rgb, cnt)).collect();
+        palette.sort_by_key(|c| core::cmp::Reverse(c.cnt));
+        Octree { quantizer: MMCQ::with_palette(palette) }
+    }
+}
+
+impl Quantizer for Octree {
+    fn palette(&self) -> &[ColorNode] {
+        &self.quantizer.quant_colors
+    }
+
+    fn quantize(&self, pixels: &[u32]) -> Vec<u32> {
+        self.quantizer.quantize(pixels)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MmcqBuilder {
+    k_max: u32,
+    representative: Representative,
+    space: DistanceSpace,
+    first_is_average: bool,
+    saturation_weighting: Option<f32>,
+    remainder_policy: RemainderPolicy,
+    neutral_anchor: bool,
+    alpha_mode: AlphaMode,
+    metric: DistanceMetric,
+    split_strategy: SplitStrategy,
+    box_selection: BoxSelection,
+    min_box_count: usize,
+}
+
+impl Default for MmcqBuilder {
+    fn default() -> MmcqBuilder {
+        MmcqBuilder {
+            k_max: 256,
+            representative: Representative::Mean,
+            space: DistanceSpace::Rgb,
+            first_is_average: false,
+            saturation_weighting: None,
+            remainder_policy: RemainderPolicy::Stop,
+            neutral_anchor: false,
+            alpha_mode: AlphaMode::Ignore,
+            metric: DistanceMetric::Euclidean,
+            split_strategy: SplitStrategy::PopulationMedian,
+            box_selection: BoxSelection::MinLevel,
+            min_box_count: 0,
+        }
+    }
+}
+
+impl MmcqBuilder {
+    pub fn new() -> MmcqBuilder {
+        Default::default()
+    }
+
+    pub fn max_colors(mut self, k_max: u32) -> MmcqBuilder {
+        // a palette has at least one color, so zero is treated as one
+        self.k_max = k_max.max(1);
+        self
+    }
+
+    pub fn representative(mut self, representative: Representative) -> MmcqBuilder {
+        self.representative = representative;
+        self
+    }
+
+    pub fn distance_space(mut self, space: DistanceSpace) -> MmcqBuilder {
+        self.space = space;
+        self
+    }
+
+    pub fn color_space(self, space: ColorSpace) -> MmcqBuilder {
+        // the spaces of the original algorithm, 'distance_space' has all of them
+        self.distance_space(space.into())
+    }
+
+    pub fn first_is_average(mut self, first_is_average: bool) -> MmcqBuilder {
+        // palette index 0 is the average color of the image, ahead of the most used colors
+        // although it stands for no pixels of its own
+        self.first_is_average = first_is_average;
+        self
+    }
+
+    pub fn saturation_weighting(mut self, power: f32) -> MmcqBuilder {
+        self.saturation_weighting = Some(power);
+        self
+    }
+
+    pub fn remainder_policy(mut self, policy: RemainderPolicy) -> MmcqBuilder {
+        // only matters together with 'min_box_count', without it every box of two or more
+        // colors splits and nothing is left over
+        self.remainder_policy = policy;
+        self
+    }
+
+    pub fn neutral_anchor(mut self, neutral_anchor: bool) -> MmcqBuilder {
+        self.neutral_anchor = neutral_anchor;
+        self
+    }
+
+    pub fn alpha_mode(mut self, alpha_mode: AlphaMode) -> MmcqBuilder {
+        self.alpha_mode = alpha_mode;
+        self
+    }
+
+    pub fn metric(mut self, metric: DistanceMetric) -> MmcqBuilder {
+        self.metric = metric;
+        self
+    }
+
+    pub fn split_strategy(mut self, strategy: SplitStrategy) -> MmcqBuilder {
+        self.split_strategy = strategy;
+        self
+    }
+
+    pub fn box_selection(mut self, selection: BoxSelection) -> MmcqBuilder {
+        self.box_selection = selection;
+        self
+    }
+
+    pub fn min_box_count(mut self, min_count: usize) -> MmcqBuilder {
+        // a box is not split if either half would stand for fewer pixels than min_count, so that
+        // a few outlier pixels are averaged into a larger box instead of taking palette entries;
+        // the palette may then have fewer than k_max colors, unless the remainder policy fills it up
+        self.min_box_count = min_count;
+        self
+    }
+
+    pub fn build_from_u32(&self, pixels: &[u32]) -> MMCQ {
+        MMCQ::from_pixels_with(pixels, self, None, None).expect("quantization cannot be canceled without a cancel flag")
+    }
+
+    pub fn build_from_u8_rgba(&self, pixels: &[u8]) -> MMCQ {
+        // panics if the buffer does not consist of whole RGBA pixels, see 'MMCQ::try_from_pixels_u8_rgba'
+        assert!(pixels.len() % 4 == 0, "{}", MmcqError::NotRgbaAligned { len: pixels.len() });
+        self.build_from_u32(&pack_pixel_bytes(pixels, PixelLayout::Rgba))
+    }
+}
+
+// Collects the color histogram chunk by chunk, for images that are processed in tiles
+// or several images that share a palette. The alpha components are ignored.
+#[derive(Default)]
+pub struct HistogramBuilder {
+    counts: ColorMap<u32, usize>,
+}
+
+impl HistogramBuilder {
+    pub fn new() -> HistogramBuilder {
+        Default::default()
+    }
+
+    pub fn add_pixels(&mut self, pixels: &[u32]) {
+        for &rgba in pixels {
+            *self.counts.entry(rgba & 0xFFFFFF).or_insert(0) += 1;
+        }
+    }
+
+    pub fn build(self, k_max: u32) -> MMCQ {
+        let mut entries: Vec<(u32, usize)> = self.counts.into_iter().collect();
+        entries.sort_unstable_by_key(|&(rgb, _)| rgb);
+        let (color_array, count_array) = entries.into_iter().unzip();
+        let options = MmcqBuilder::new().max_colors(k_max);
+        MMCQ::from_histogram_with(ColorHistogram::new(color_array, count_array), &options, None, None).expect("quantization cannot be canceled without a cancel flag")
+    }
 }
@@ -0,0 +1,92 @@
+// Octree color quantization.
+//
+// Every color is inserted down to the depth of 8, one level per bit of the channels from
+// the most significant on, so the leaves are the exact image colors. Every node keeps the
+// channel sums and the pixel count of its subtree. The nodes of the deepest level left are
+// then turned into leaves, the ones standing for the fewest pixels first, until there are
+// no more leaves than the palette can take. A merge removes up to 7 leaves at once, so the
+// palette may end up smaller than asked for.
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+const DEPTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Node {
+    children: [usize; 8], // indices into the node array, 0 for none as the root is nobody's child
+    sum: [u64; 3],
+    count: u64,
+    leaf: bool,
+}
+
+impl Node {
+    fn add(&mut self, channels: [u32; 3], cnt: usize) {
+        for (sum, value) in self.sum.iter_mut().zip(channels) {
+            *sum += value as u64 * cnt as u64;
+        }
+        self.count += cnt as u64;
+    }
+
+    fn mean(&self) -> u32 {
+        let mean = |c: usize| ((self.sum[c] + self.count / 2) / self.count) as u32;
+        mean(0) | mean(1) << 8 | mean(2) << 16
+    }
+}
+
+pub fn reduce(colors: &[u32], counts: &[usize], k_max: usize) -> Vec<(u32, usize)> {
+    // the colors are expected to be distinct, every one of them makes a leaf;
+    // returns the packed mean color and the pixel count of every leaf left
+    let mut nodes = vec![Node::default()];
+    let mut levels: Vec<Vec<usize>> = vec![Vec::new(); DEPTH]; // inner nodes by depth
+    levels[0].push(0);
+    for (&rgb, &cnt) in colors.iter().zip(counts) {
+        let channels = [rgb & 0xFF, (rgb >> 8) & 0xFF, (rgb >> 16) & 0xFF];
+        let mut node = 0;
+        for depth in 0..DEPTH {
+            nodes[node].add(channels, cnt);
+            let shift = 7 - depth;
+            let child = ((channels[0] >> shift) & 1) << 2 | ((channels[1] >> shift) & 1) << 1 | ((channels[2] >> shift) & 1);
+            if nodes[node].children[child as usize] == 0 {
+                nodes.push(Node { leaf: depth + 1 == DEPTH, ..Default::default() });
+                nodes[node].children[child as usize] = nodes.len() - 1;
+                if depth + 1 < DEPTH {
+                    levels[depth + 1].push(nodes.len() - 1);
+                }
+            }
+            node = nodes[node].children[child as usize];
+        }
+        nodes[node].add(channels, cnt);
+    }
+
+    let mut leaves = colors.len();
+    for depth in (0..DEPTH).rev() {
+        if leaves <= k_max {
+            break;
+        }
+        // all children of the nodes on this level are leaves by now
+        let mut reducible = core::mem::take(&mut levels[depth]);
+        reducible.sort_by_key(|&i| (nodes[i].count, i));
+        for i in reducible {
+            if leaves <= k_max {
+                break;
+            }
+            let merged = nodes[i].children.iter().filter(|&&c| c != 0).count();
+            nodes[i].children = [0; 8];
+            nodes[i].leaf = true;
+            leaves = leaves + 1 - merged;
+        }
+    }
+
+    let mut palette = Vec::with_capacity(leaves);
+    let mut stack = vec![0];
+    while let Some(i) = stack.pop() {
+        let node = nodes[i];
+        if node.leaf {
+            palette.push((node.mean(), node.count as usize));
+        } else {
+            stack.extend(node.children.iter().rev().filter(|&&c| c != 0));
+        }
+    }
+    palette
+}
@@ -0,0 +1,1517 @@
+use super::*;
+
+#[test]
+fn color_reduction() {
+    let pixels: Vec<u32> = (0..1000u32).map(|i| i * 16411).collect();
+    let m = MMCQ::from_pixels_u32_rgba(&pixels, 16);
+    assert_eq!(m.color_reduction(), (1000, 16));
+}
+
+#[test]
+fn from_centroids_maps_to_nearest() {
+    let mut m = MMCQ::from_centroids(&[(255, 0, 0, 10), (0, 0, 255, 5), (10, 200, 10, 1)]);
+    assert_eq!(m.palette_with_counts(), vec![((255, 0, 0), 10), ((0, 0, 255), 5), ((10, 200, 10), 1)]);
+    let out = m.quantize_image(&vec![0x1010F0, 0xE00000, 0x20C020]);
+    assert_eq!(out, vec![0x0000FF, 0xFF0000, 0x0AC80A]);
+}
+
+#[test]
+fn median_representative_ignores_outliers() {
+    // a gray cluster with a few white outliers
+    let mut pixels = vec![0x646464u32; 10];
+    pixels.extend(vec![0x666666u32; 5]);
+    pixels.extend(vec![0xFFFFFFu32; 4]);
+    let mean = MmcqBuilder::new().max_colors(1).build_from_u32(&pixels);
+    let median = MmcqBuilder::new().max_colors(1).representative(Representative::Median).build_from_u32(&pixels);
+    assert!(mean.palette_rgb()[0].0 > 130);
+    assert_eq!(median.palette_rgb(), vec![(100, 100, 100)]);
+}
+
+#[test]
+fn max_palette_bytes() {
+    let pixels: Vec<u32> = (0..1000u32).map(|i| i * 16411).collect();
+    assert_eq!(MMCQ::from_pixels_max_palette_bytes(&pixels, 48).get_quantized_colors().len(), 16);
+    assert_eq!(MMCQ::from_pixels_max_palette_bytes(&pixels, 50).get_quantized_colors().len(), 16);
+    assert_eq!(MMCQ::from_pixels_max_palette_bytes(&pixels, 3).get_quantized_colors().len(), 1);
+    for max_bytes in 0..3 {
+        assert!(MMCQ::from_pixels_max_palette_bytes(&pixels, max_bytes).get_quantized_colors().is_empty());
+    }
+}
+
+#[test]
+fn blend_on_palette_entry() {
+    let m = MMCQ::from_centroids(&[(255, 0, 0, 10), (0, 0, 255, 5)]);
+    let blend = m.quantize_to_blend(&[0x0000FF, 0xFF0000, 0x800080]);
+    assert_eq!(blend[0], (0, 1, 0.0));
+    assert_eq!(blend[1], (1, 0, 0.0));
+    assert!((blend[2].2 - 0.5).abs() < 0.01);
+}
+
+#[test]
+fn blend_starts_on_the_mapped_color() {
+    let pixels: Vec<u32> = (0..4096u32).map(|i| i.wrapping_mul(2654435761) >> 8).collect();
+    let builders = [MmcqBuilder::new().max_colors(8).distance_space(DistanceSpace::Oklab), MmcqBuilder::new().max_colors(8).metric(DistanceMetric::Redmean)];
+    for builder in &builders {
+        let m = builder.build_from_u32(&pixels);
+        let blend = m.quantize_to_blend(&pixels);
+        for (i, &rgb) in pixels.iter().enumerate() {
+            let (r, g, b) = ColorNode::unpack_rgb(rgb);
+            assert_eq!(blend[i].0, m.nearest_index(r, g, b));
+        }
+    }
+}
+
+#[test]
+fn gpl_palette() {
+    let m = MMCQ::from_gpl("GIMP Palette\nName: x\nColumns: 2\n#\n255   0   0\tRed\n  0 128 255 Sky blue\n").unwrap();
+    assert_eq!(m.palette_rgb(), vec![(255, 0, 0), (0, 128, 255)]);
+    assert!(MMCQ::from_gpl("nope").is_err());
+    assert_eq!(MMCQ::from_gpl("GIMP Palette\n1 2\n").err(), Some(ParseError::InvalidColor { line: 2 }));
+}
+
+#[test]
+fn masked_dither_leaves_unmasked_pixels_undithered() {
+    let mut m = MMCQ::from_centroids(&[(0, 0, 0, 1), (255, 255, 255, 1)]);
+    let width = 8;
+    let pixels = vec![0x808080u32; 64];
+    let mask: Vec<bool> = (0..64).map(|i| i % width < 4).collect();
+    let dithered = m.quantize_image_dithered_masked(&pixels, width, &mask).unwrap();
+    let plain = m.quantize_image(&pixels);
+    for i in 0..64 {
+        if !mask[i] {
+            assert_eq!(dithered[i], plain[i]);
+        }
+    }
+    let masked: Vec<u32> = (0..64).filter(|&i| mask[i]).map(|i| dithered[i]).collect();
+    assert!(masked.contains(&0) && masked.contains(&0xFFFFFF));
+    assert_eq!(m.quantize_image_dithered_masked(&pixels, 0, &mask), Err(MmcqError::NotRowAligned { len: 64, width: 0 }));
+    assert_eq!(m.quantize_image_dithered_masked(&pixels[..60], 8, &mask[..60]), Err(MmcqError::NotRowAligned { len: 60, width: 8 }));
+}
+
+#[test]
+fn parent_palette_indices() {
+    let m = MMCQ::from_centroids(&[(250, 0, 0, 1), (10, 10, 10, 1)]);
+    let parent = [(0, 0, 0), (255, 255, 255), (255, 0, 0)];
+    assert_eq!(m.palette_as_parent_indices(&parent), vec![2, 0]);
+}
+
+#[test]
+fn stretched_palette_spans_full_range() {
+    let mut m = MMCQ::from_centroids(&[(100, 50, 7, 1), (120, 60, 7, 1), (110, 55, 7, 1)]);
+    m.stretch_palette();
+    assert_eq!(m.palette_rgb(), vec![(0, 0, 7), (255, 255, 7), (127, 127, 7)]);
+    assert_eq!(m.quantize_image(&vec![0x00FFFF]), vec![0x07FFFF]);
+}
+
+#[test]
+fn distance2_packed_matches_unpacked() {
+    let node = ColorNode::new_colors(12, 200, 99, 1);
+    for &rgb in &[0x000000u32, 0xFFFFFF, 0x63C80C, 0xFF63C80C, 0x0C00FF, 0x123456] {
+        let (red, grn, blu) = ((rgb & 0xFF) as u8, ((rgb >> 8) & 0xFF) as u8, ((rgb >> 16) & 0xFF) as u8);
+        assert_eq!(node.distance2_packed(rgb), node.distance2(red, grn, blu));
+    }
+    assert_eq!(node.distance2_packed(0x63C80C), 0);
+}
+
+#[test]
+fn oklab_quantization_has_lower_oklab_error() {
+    let mut pixels = Vec::new();
+    for i in 0..256u32 {
+        for j in 0..64u32 {
+            pixels.push(i | (j * 4) << 8 | i << 16);
+        }
+    }
+    let error = |space| {
+        let mut m = MmcqBuilder::new().max_colors(16).distance_space(space).build_from_u32(&pixels);
+        let out = m.quantize_image(&pixels);
+        let oklab = |rgb: u32| {
+            let (red, grn, blu) = ColorNode::unpack_rgb(rgb);
+            color::srgb_to_oklab(red, grn, blu)
+        };
+        pixels.iter().zip(&out).map(|(&a, &b)| color::oklab_distance(oklab(a), oklab(b))).sum::<f64>() / pixels.len() as f64
+    };
+    assert!(error(DistanceSpace::Oklab) < error(DistanceSpace::Rgb));
+    for c in 0..=255u8 {
+        assert_eq!(color::oklab_to_srgb(color::srgb_to_oklab(c, 255 - c, c / 2)), (c, 255 - c, c / 2));
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn batch_parallel_matches_serial() {
+    let a: Vec<u32> = (0..5000u32).map(|i| i * 7919).collect();
+    let b: Vec<u32> = (0..3000u32).map(|i| i * 104729).collect();
+    let mut m = MMCQ::from_pixels_u32_rgba(&a, 16);
+    let parallel = m.quantize_batch_parallel(&[&a, &b]);
+    assert_eq!(parallel, vec![m.quantize_image(&a), m.quantize_image(&b)]);
+}
+
+#[test]
+fn perceptually_equal_colors() {
+    let gray = ColorNode::new_colors(100, 100, 100, 1);
+    assert!(gray.perceptually_equal(&ColorNode::new_colors(101, 101, 101, 1), 2.3));
+    assert!(!ColorNode::new_colors(255, 0, 0, 1).perceptually_equal(&ColorNode::new_colors(0, 255, 0, 1), 2.3));
+    let white = color::srgb_to_lab(255, 255, 255);
+    assert!((white[0] - 100.0).abs() < 0.01 && white[1].abs() < 0.01 && white[2].abs() < 0.01);
+}
+
+#[test]
+fn canceled_before_the_call() {
+    let pixels: Vec<u32> = (0..5000u32).map(|i| i * 7919).collect();
+    assert!(MMCQ::from_pixels_cancellable(&pixels, 16, &AtomicBool::new(true)).is_none());
+    let m = MMCQ::from_pixels_cancellable(&pixels, 16, &AtomicBool::new(false)).unwrap();
+    assert_eq!(m.get_quantized_colors(), MMCQ::from_pixels_u32_rgba(&pixels, 16).get_quantized_colors());
+}
+
+#[test]
+fn indexed_debug_string() {
+    let m = MMCQ::from_centroids(&[(0, 0, 0, 1), (255, 255, 255, 1)]);
+    assert_eq!(m.to_indexed_debug_string(&[0, 0xFFFFFF, 0xF0F0F0, 0x101010], 2), Ok("01\n10\n".to_string()));
+    let wide: Vec<_> = (0..20u8).map(|i| (i * 10, 0, 0, 1)).collect();
+    let m = MMCQ::from_centroids(&wide);
+    assert_eq!(m.to_indexed_debug_string(&[0, 190, 0, 10], 2), Ok("00 13\n00 01\n".to_string()));
+    assert_eq!(m.to_indexed_debug_string(&[0, 190, 0], 2), Err(MmcqError::NotRowAligned { len: 3, width: 2 }));
+    assert_eq!(m.to_indexed_debug_string(&[0, 190], 0), Err(MmcqError::NotRowAligned { len: 2, width: 0 }));
+}
+
+#[test]
+fn region_budget() {
+    // reds in the region, blues outside of it
+    let mut pixels: Vec<u32> = (0..100u32).collect();
+    pixels.extend((0..100u32).map(|i| i << 16));
+    let inside = |m: &MMCQ| m.palette_rgb().iter().filter(|c| c.1 == 0 && c.2 == 0).count();
+    for budget in 1..8 {
+        let m = MMCQ::from_pixels_region_budget(&pixels, 8, &[((0, 0, 0), (255, 0, 0), budget)]);
+        assert_eq!(m.get_quantized_colors().len(), 8);
+        assert_eq!(inside(&m), budget as usize);
+        assert_eq!(m.validate(), Ok(()));
+    }
+
+    // the colors outside of the regions keep an entry when the regions take all of k_max
+    let noise: Vec<u32> = (0..1000u32).map(|i| i.wrapping_mul(2654435761) >> 8).collect();
+    let m = MMCQ::from_pixels_region_budget(&noise, 4, &[((0, 0, 0), (100, 100, 100), 4)]);
+    assert_eq!(m.get_quantized_colors().len(), 4);
+    assert_eq!(m.validate(), Ok(()));
+
+    // budgets beyond k_max are cut down, regions without a budget share the remainder
+    let regions = [((0, 0, 0), (127, 255, 255), 6), ((128, 0, 0), (255, 127, 255), 6), ((128, 128, 0), (255, 255, 255), 6)];
+    for k_max in 1..10 {
+        let m = MMCQ::from_pixels_region_budget(&noise, k_max, &regions);
+        assert_eq!(m.get_quantized_colors().len(), k_max as usize);
+        assert_eq!(m.validate(), Ok(()));
+    }
+    let m = MMCQ::from_pixels_region_budget(&noise, 8, &[((0, 0, 0), (127, 255, 255), 0)]);
+    assert_eq!(m.get_quantized_colors().len(), 8);
+    assert_eq!(m.validate(), Ok(()));
+}
+
+#[test]
+fn cluster_spreads() {
+    let m = MMCQ::from_pixels_u32_rgba(&[0x102030u32; 10], 4);
+    assert_eq!(m.cluster_spreads(), vec![[0.0; 3]]);
+
+    // two colors 20 apart in red make one box, their deviation from the mean is 10
+    let m = MMCQ::from_pixels_u32_rgba(&[0x000000, 0x000014], 1);
+    assert_eq!(m.cluster_spreads(), vec![[10.0, 0.0, 0.0]]);
+
+    let pixels: Vec<u32> = (0..1000u32).map(|i| i * 16411).collect();
+    let m = MMCQ::from_pixels_u32_rgba(&pixels, 8);
+    let spreads = m.cluster_spreads();
+    assert_eq!(spreads.len(), 8);
+    assert!(spreads.iter().all(|s| s.iter().all(|&d| d > 0.0)));
+
+    // a reduced palette has no boxes, its spreads come from the closest palette colors
+    let mut m = MMCQ::from_pixels_u32_rgba(&[0x000000, 0x000014, 0xFF0000, 0xFF0000], 3);
+    m.reduce_palette(2);
+    assert_eq!(m.validate(), Ok(()));
+    assert_eq!(m.palette_rgb(), vec![(0, 0, 255), (10, 0, 0)]);
+    assert_eq!(m.cluster_spreads(), vec![[0.0; 3], [10.0, 0.0, 0.0]]);
+
+    assert_eq!(MMCQ::from_centroids(&[(0, 0, 0, 1), (255, 255, 255, 1)]).cluster_spreads(), vec![[0.0; 3]; 2]);
+}
+
+#[test]
+fn dirty_rect() {
+    let m = MMCQ::from_centroids(&[(0, 0, 0, 1), (255, 255, 255, 1)]);
+    let mut pixels = vec![0u32; 12];
+    let (indices, rect) = m.quantize_indices_dirty(&[], &pixels, 4).unwrap();
+    assert_eq!(rect, Some(Rect { x: 0, y: 0, width: 4, height: 3 }));
+    let (unchanged, rect) = m.quantize_indices_dirty(&indices, &pixels, 4).unwrap();
+    assert_eq!(rect, None);
+    assert_eq!(unchanged, indices);
+    pixels[6] = 0xFFFFFF;
+    let (_, rect) = m.quantize_indices_dirty(&indices, &pixels, 4).unwrap();
+    assert_eq!(rect, Some(Rect { x: 2, y: 1, width: 1, height: 1 }));
+    assert_eq!(m.quantize_indices_dirty(&indices, &pixels, 0), Err(MmcqError::NotRowAligned { len: 12, width: 0 }));
+    assert_eq!(m.quantize_indices_dirty(&indices, &pixels, 5), Err(MmcqError::NotRowAligned { len: 12, width: 5 }));
+}
+
+#[test]
+fn gamma_space_nearest_differs_for_dark_colors() {
+    // 44 is closer to 80 than to 0, but not once the channels are gamma expanded
+    let query = vec![0x2C2C2Cu32];
+    let mut linear = MMCQ::from_centroids(&[(0, 0, 0, 1), (80, 80, 80, 1)]);
+    assert_eq!(linear.quantize_image(&query), vec![0x505050]);
+    let mut gamma = MmcqBuilder::new().max_colors(2).distance_space(DistanceSpace::Gamma(2.2)).build_from_u32(&[0, 0x505050, 0x505050]);
+    assert_eq!(gamma.palette_rgb(), vec![(80, 80, 80), (0, 0, 0)]);
+    assert_eq!(gamma.quantize_image(&query), vec![0x000000]);
+}
+
+#[test]
+fn align_to_reference() {
+    let pixels: Vec<u32> = (0..1000u32).map(|i| i * 16411).collect();
+    let m = MMCQ::from_pixels_u32_rgba(&pixels, 16);
+    let own = m.palette_rgb();
+    assert_eq!(m.align_to(&own), (0..16).collect::<Vec<_>>());
+    let reversed: Vec<_> = own.iter().rev().cloned().collect();
+    assert_eq!(m.align_to(&reversed), (0..16).rev().collect::<Vec<_>>());
+    let mut partial = m.align_to(&own[..3]);
+    assert_eq!(&partial[..3], &[0, 1, 2]);
+    partial.sort();
+    assert_eq!(partial, (0..16).collect::<Vec<_>>());
+}
+
+#[test]
+fn flicker_ignores_palette_order() {
+    let a = [(1u8, 2u8, 3u8), (200, 100, 0), (0, 0, 255)];
+    let b = [(0, 0, 255), (1, 2, 3), (200, 100, 0)];
+    assert_eq!(palette_flicker(&a, &a), 0.0);
+    assert_eq!(palette_flicker(&a, &b), 0.0);
+    assert!(palette_flicker(&a, &[(0, 0, 0)]) > 0.0);
+}
+
+#[test]
+fn priority_color_maps_to_itself() {
+    let mut pixels: Vec<u32> = (0..5000u32).map(|i| i * 7919).collect();
+    pixels.push(0x281EC8);
+    pixels.push(0x281EC9);
+    let mut m = MMCQ::from_pixels_priority_color(&pixels, 16, (200, 30, 40), 3);
+    assert_eq!(m.get_quantized_colors().len(), 16);
+    assert_eq!(m.quantize_image(&vec![0x281EC8]), vec![0x281EC8]);
+    assert_eq!(m.validate(), Ok(()));
+    // the priority color does not even have to be in the image
+    let mut m = MMCQ::from_pixels_priority_color(&pixels[..100], 16, (1, 2, 3), 3);
+    assert_eq!(m.quantize_image(&vec![0x030201]), vec![0x030201]);
+
+    // extra is cut down to k_max, every color stays boxed
+    for &(k_max, extra) in &[(1, 0), (1, 3), (2, 3), (2, 0), (4, 1), (4, 3), (4, 10)] {
+        let mut m = MMCQ::from_pixels_priority_color(&pixels, k_max, (200, 30, 40), extra);
+        assert_eq!(m.get_quantized_colors().len(), k_max as usize, "{} {}", k_max, extra);
+        assert_eq!(m.validate(), Ok(()));
+        assert_eq!(m.palette_with_counts().iter().map(|&(_, cnt)| cnt).sum::<usize>(), pixels.len());
+        assert_eq!(m.quantize_image(&vec![0x281EC8]), vec![0x281EC8]);
+    }
+
+    // a single color near the priority one leaves the rest of 'extra' to the far colors
+    let mut few_near: Vec<u32> = (0..500u32).map(|i| (i * 16411) & 0x7F7F7F).collect();
+    few_near.extend(&[0x281EC8, 0x281EC9]);
+    assert_eq!(MMCQ::from_pixels_priority_color(&few_near, 8, (200, 30, 40), 5).get_quantized_colors().len(), 8);
+    // and the other way around
+    let mut few_far: Vec<u32> = (0..500u32).map(|i| ColorNode::pack_rgb(190 + (i % 20) as u8, 20 + (i / 20 % 20) as u8, 40)).collect();
+    few_far.push(0x000000);
+    let m = MMCQ::from_pixels_priority_color(&few_far, 8, (200, 30, 40), 2);
+    assert_eq!(m.get_quantized_colors().len(), 8);
+    assert_eq!(m.validate(), Ok(()));
+}
+
+#[test]
+fn ssim_of_exact_match_is_one() {
+    let pixels: Vec<u32> = (0..400u32).map(|i| if (i / 7) % 3 == 0 { 0x0000FF } else { 0x00FF00 }).collect();
+    let m = MMCQ::from_centroids(&[(255, 0, 0, 1), (0, 255, 0, 1)]);
+    assert!((m.ssim(&pixels, 20, 20) - 1.0).abs() < 1e-9);
+    let m = MMCQ::from_centroids(&[(255, 0, 0, 1)]);
+    assert!(m.ssim(&pixels, 20, 20) < 0.9);
+    assert!((m.ssim(&[0x0000FF; 6], 3, 2) - 1.0).abs() < 1e-9);
+}
+
+#[test]
+fn first_is_average() {
+    let pixels: Vec<u32> = (0..1000u32).map(|i| (i % 256) | ((i * 7) % 256) << 8 | ((i * 13) % 256) << 16).collect();
+    let m = MmcqBuilder::new().max_colors(16).first_is_average(true).build_from_u32(&pixels);
+    let counts = m.palette_with_counts();
+    assert_eq!(counts.len(), 16);
+    let mean = |shift: u32| pixels.iter().map(|&p| ((p >> shift) & 0xFF) as f64).sum::<f64>() / pixels.len() as f64;
+    let ((red, grn, blu), cnt) = counts[0];
+    assert!((red as f64 - mean(0)).abs() <= 0.5 && (grn as f64 - mean(8)).abs() <= 0.5 && (blu as f64 - mean(16)).abs() <= 0.5);
+    assert_eq!(cnt, 0);
+    assert!(counts[1..].windows(2).all(|w| w[0].1 >= w[1].1));
+    assert_eq!(m.validate(), Ok(()));
+}
+
+#[test]
+fn atlas_tiles_reference_global_palette() {
+    let a = [0x000000u32, 0xFF00FF, 0x000000];
+    let b = [0x00FF00u32, 0x800080, 0xFFFFFF];
+    let (palette, tiles) = MMCQ::quantize_atlas(&[&a, &b], 4);
+    assert_eq!(palette.len(), 5);
+    assert_eq!(tiles[0].0, 0);
+    assert_eq!(tiles[1].0, 2);
+    for (tile, &(offset, ref indices)) in [&a[..], &b[..]].iter().zip(tiles.iter()) {
+        for (&rgb, &idx) in tile.iter().zip(indices) {
+            let (red, grn, blu) = palette[offset + idx as usize];
+            assert_eq!(ColorNode::pack_rgb(red, grn, blu), rgb);
+        }
+    }
+}
+
+#[test]
+fn redundant_for_batch() {
+    let m = MMCQ::from_centroids(&[(0, 0, 0, 1), (255, 255, 255, 1), (0, 255, 0, 1)]);
+    assert_eq!(m.redundant_for_batch(&[&[0x000000, 0x101010], &[0x00F000]]), vec![1]);
+    assert!(m.redundant_for_batch(&[&[0x000000, 0xFFFFFF], &[0x00F000]]).is_empty());
+}
+
+#[test]
+fn bilateral_prepass_reduces_stray_colors() {
+    // a dark and a light half, both with noise
+    let (width, height) = (32usize, 32usize);
+    let mut seed = 12345u32;
+    let mut pixels = Vec::new();
+    for _ in 0..height {
+        for x in 0..width {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let v = if x < 16 { 40 } else { 200 } + (seed >> 16) % 9 - 4;
+            pixels.push(v * 0x010101);
+        }
+    }
+    let plain = MMCQ::from_pixels_u32_rgba(&pixels, 64);
+    let filtered = MMCQ::from_pixels_bilateral(&pixels, width, height, 64, 1.5, 20.0);
+    assert!(filtered.color_reduction().0 < plain.color_reduction().0);
+    // the edge survives
+    let palette = filtered.palette_rgb();
+    assert!(palette.iter().any(|c| c.0 < 60) && palette.iter().any(|c| c.0 > 180));
+}
+
+#[test]
+fn fresh_quantizer_validates() {
+    let pixels: Vec<u32> = (0..10u32).collect();
+    assert_eq!(MMCQ::from_pixels_u32_rgba(&pixels, 16).validate(), Ok(()));
+    let pixels: Vec<u32> = (0..1000u32).map(|i| i * 16411).collect();
+    assert_eq!(MMCQ::from_pixels_u32_rgba(&pixels, 16).validate(), Ok(()));
+    assert!(MMCQ::from_centroids(&[]).validate().is_err());
+}
+
+#[test]
+fn rgb565_palette() {
+    let pixels: Vec<u32> = (0..5000u32).map(|i| i * 7919).collect();
+    let m = MMCQ::from_pixels_bitdepths(&pixels, 32, 5, 6, 5);
+    let representable = |value: u8, bits: u32| {
+        let max = (1u32 << bits) - 1;
+        (0..=max).any(|level| ((level * 255 + max / 2) / max) as u8 == value)
+    };
+    assert_eq!(m.get_quantized_colors().len(), 32);
+    for (red, grn, blu) in m.palette_rgb() {
+        assert!(representable(red, 5) && representable(grn, 6) && representable(blu, 5));
+    }
+}
+
+#[test]
+fn bmp_parses_back() {
+    let m = MMCQ::from_centroids(&[(0, 0, 0, 1), (255, 255, 255, 1), (10, 200, 30, 1)]);
+    let pixels = [0u32, 0xFFFFFF, 0x1EC80A, 0x1EC80A, 0, 0xFFFFFF];
+    let bmp = m.to_bmp_indexed(&pixels, 3, 2).unwrap();
+    let read = |o: usize| u32::from_le_bytes([bmp[o], bmp[o + 1], bmp[o + 2], bmp[o + 3]]) as usize;
+    assert_eq!(&bmp[0..2], b"BM");
+    assert_eq!(read(2), bmp.len());
+    assert_eq!((read(18), read(22), read(46)), (3, 2, 3));
+    // BGRX palette entries
+    let palette: Vec<_> = (0..read(46)).map(|i| (bmp[54 + 4 * i + 2], bmp[54 + 4 * i + 1], bmp[54 + 4 * i])).collect();
+    assert_eq!(palette, m.palette_rgb());
+    // bottom-up rows, padded to 4 bytes
+    let offset = read(10);
+    assert_eq!(bmp.len(), offset + 8);
+    let rows: Vec<&[u8]> = bmp[offset..].chunks(4).map(|row| &row[..3]).rev().collect();
+    let indices: Vec<u8> = rows.concat();
+    assert_eq!(indices, m.quantize_to_indices(&pixels).0);
+    assert_eq!(indices, vec![0, 1, 2, 2, 0, 1]);
+
+    // the padded size does not fit into the 32-bit size fields
+    assert_eq!(m.to_bmp_indexed(&[], u32::MAX, 1), Err(MmcqError::TooLargeForBmp { width: u32::MAX, height: 1 }));
+    assert_eq!(m.to_bmp_indexed(&[], 1 << 16, 1 << 16), Err(MmcqError::TooLargeForBmp { width: 1 << 16, height: 1 << 16 }));
+}
+
+#[test]
+fn lossless_k() {
+    let pixels = [1u32, 2, 3, 4, 5, 1, 2, 0xFF000001];
+    assert_eq!(min_lossless_k(&pixels, 256), Some(5));
+    assert_eq!(min_lossless_k(&pixels, 5), Some(5));
+    let pixels: Vec<u32> = (0..1000u32).collect();
+    assert_eq!(min_lossless_k(&pixels, 16), None);
+}
+
+#[test]
+fn saturation_weighting_keeps_vivid_accent() {
+    let mut pixels: Vec<u32> = (0..2000u32).map(|i| ((i % 200) + 20) * 0x010101).collect();
+    pixels.extend((0..6u32).map(|i| 0x0000F0 + i));
+    let has_red = |m: &MMCQ| m.palette_rgb().iter().any(|c| c.0 > 200 && c.1 < 60 && c.2 < 60);
+    assert!(!has_red(&MmcqBuilder::new().max_colors(4).build_from_u32(&pixels)));
+    assert!(has_red(&MmcqBuilder::new().max_colors(4).saturation_weighting(1.0).build_from_u32(&pixels)));
+}
+
+#[test]
+fn duotone_ends() {
+    let out = duotone(&[0x000000, 0xFFFFFF, 0xFF000000], (10, 20, 30), (200, 150, 100));
+    assert_eq!(out, vec![0x1E140A, 0x6496C8, 0x1E140A]);
+}
+
+#[test]
+fn channel_steps_follow_palette_density() {
+    // dense in red, sparse in blue
+    let m = MMCQ::from_centroids(&[(0, 0, 0, 1), (32, 0, 0, 1), (64, 0, 255, 1), (96, 0, 255, 1)]);
+    let (red, grn, blu) = m.channel_steps();
+    assert!(red < blu);
+    assert_eq!((red, grn, blu), (32.0, 0.0, 255.0));
+}
+
+#[test]
+fn subdivide_largest_uses_remaining_budget() {
+    // a flat gray with a few single pixel outliers, too small to be split off
+    let mut pixels = vec![0x808080u32; 1000];
+    for (i, &rgb) in [0xFF0000u32, 0x00FF00, 0x0000FF, 0xFFFFFF, 0x000000].iter().enumerate() {
+        pixels[i * 200 + 7] = rgb;
+    }
+    let builder = MmcqBuilder::new().max_colors(4).min_box_count(10);
+    let stop = builder.build_from_u32(&pixels);
+    let subdivided = builder.remainder_policy(RemainderPolicy::SubdivideLargest).build_from_u32(&pixels);
+    assert_eq!(stop.get_quantized_colors().len(), 1);
+    assert_eq!(subdivided.get_quantized_colors().len(), 4);
+    assert_eq!(subdivided.validate(), Ok(()));
+
+    // without the threshold every box splits, k_max is reached either way
+    let builder = MmcqBuilder::new().max_colors(4);
+    assert_eq!(builder.build_from_u32(&pixels).get_quantized_colors().len(), 4);
+    assert_eq!(builder.remainder_policy(RemainderPolicy::SubdivideLargest).build_from_u32(&pixels).palette_rgb(), builder.build_from_u32(&pixels).palette_rgb());
+}
+
+#[test]
+fn map_palette_invert() {
+    let mut m = MMCQ::from_centroids(&[(10, 20, 30, 3), (200, 100, 0, 2)]);
+    let before = m.palette_rgb();
+    m.map_palette(|(r, g, b)| (255 - r, 255 - g, 255 - b));
+    for (&(r, g, b), inverted) in before.iter().zip(m.palette_rgb()) {
+        assert_eq!(inverted, (255 - r, 255 - g, 255 - b));
+    }
+    assert_eq!(m.quantize_image(&vec![0xE1EBF5]), vec![0xE1EBF5]);
+}
+
+#[cfg(feature = "tracing")]
+mod span_names {
+    use std::sync::{Arc, Mutex};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    // a subscriber recording the names of the spans created
+    pub struct SpanNames(pub Arc<Mutex<Vec<String>>>);
+
+    impl Subscriber for SpanNames {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes) -> Id {
+            let mut names = self.0.lock().unwrap();
+            names.push(span.metadata().name().to_string());
+            Id::from_u64(names.len() as u64)
+        }
+
+        fn record(&self, _: &Id, _: &Record) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event) {}
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn phase_spans() {
+    let names = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let pixels: Vec<u32> = (0..1000u32).map(|i| i * 16411).collect();
+    tracing::subscriber::with_default(span_names::SpanNames(names.clone()), || MMCQ::from_pixels_u32_rgba(&pixels, 16));
+    let names = names.lock().unwrap();
+    assert_eq!(*names, vec!["histogram", "split", "average"]);
+}
+
+#[test]
+fn transparency_mask_follows_alpha_threshold() {
+    let m = MMCQ::from_centroids(&[(0, 0, 0, 1), (255, 255, 255, 1)]);
+    let (indices, mask) = m.quantize_with_transparency_mask(&[0x000000, 0xFFFFFF, 0x101010, 0xF0F0F0], &[0, 200, 128, 127], 128);
+    assert_eq!(indices, vec![0, 1, 0, 1]);
+    assert_eq!(mask, vec![true, false, false, true]);
+}
+
+#[test]
+fn gray_chroma_points_are_neutral() {
+    let m = MMCQ::from_centroids(&[(0, 0, 0, 1), (90, 90, 90, 1), (255, 255, 255, 1)]);
+    for (cb, cr) in m.chroma_points() {
+        assert!((cb - 128.0).abs() < 0.5 && (cr - 128.0).abs() < 0.5);
+    }
+    let (cb, cr) = MMCQ::from_centroids(&[(255, 0, 0, 1)]).chroma_points()[0];
+    assert!(cb < 128.0 && cr > 200.0);
+}
+
+#[test]
+fn codec_round_trip() {
+    let pixels: Vec<u32> = (0..1000u32).map(|i| i * 16411).collect();
+    let mut m = MMCQ::from_pixels_u32_rgba(&pixels, 16);
+    let codec = m.build_codec();
+    assert_eq!(codec.palette().len(), 16);
+    assert_eq!(codec.decode(&codec.encode(&pixels)), m.quantize_image(&pixels));
+}
+
+#[test]
+fn alpha_variants_of_a_color_merge() {
+    // opaque red and 90% opaque red, next to a half transparent green
+    let mut pixels = vec![0xFF0000FFu32; 50];
+    pixels.extend(vec![0xE60000FFu32; 50]);
+    pixels.extend(vec![0x8000FF00u32; 20]);
+    for &mode in &[AlphaMode::Ignore, AlphaMode::Preserve, AlphaMode::Premultiply] {
+        let m = MMCQ::from_pixels_u32_rgba_with_alpha(&pixels, 4, mode);
+        let counts = m.palette_with_counts();
+        assert_eq!(counts.len(), 2, "{:?}", mode);
+        assert_eq!(counts[0].1, 100);
+        assert_eq!(m.validate(), Ok(()));
+    }
+    // premultiplied with the mean alpha of the two
+    let m = MMCQ::from_pixels_u32_rgba_with_alpha(&pixels, 4, AlphaMode::Premultiply);
+    assert_eq!(m.palette_rgb()[0], (243, 0, 0));
+
+    // alphas far apart stay apart
+    let mut pixels = vec![0xFF0000FFu32; 50];
+    pixels.extend(vec![0x400000FFu32; 50]);
+    assert_eq!(MMCQ::from_pixels_u32_rgba_with_alpha(&pixels, 4, AlphaMode::Premultiply).get_quantized_colors().len(), 2);
+
+    // a full alpha ramp of one color does not chain into a single group
+    let pixels: Vec<u32> = (0..256u32).map(|a| a << 24 | 0x0000FF).collect();
+    let m = MMCQ::from_pixels_u32_rgba_with_alpha(&pixels, 16, AlphaMode::Premultiply);
+    assert!(m.get_quantized_colors().len() >= 4);
+}
+
+#[test]
+fn adaptive_dither_leaves_exact_regions_alone() {
+    // the left half holds palette colors only, the right half is mid gray
+    let m = MMCQ::from_centroids(&[(0, 0, 0, 1), (255, 255, 255, 1)]);
+    let mut pixels = Vec::new();
+    for y in 0..8 {
+        for x in 0..8 {
+            pixels.push(if x >= 4 {
+                0x808080
+            } else if (x + y) % 2 == 0 {
+                0
+            } else {
+                0xFFFFFF
+            });
+        }
+    }
+    let out = m.quantize_image_adaptive_dither(&pixels, 8).unwrap();
+    for y in 0..8 {
+        assert_eq!(out[y * 8..y * 8 + 4], pixels[y * 8..y * 8 + 4]);
+    }
+    let right: Vec<u32> = (0..8).flat_map(|y| out[y * 8 + 4..y * 8 + 8].to_vec()).collect();
+    assert!(right.contains(&0) && right.contains(&0xFFFFFF));
+    assert_eq!(m.quantize_image_adaptive_dither(&pixels, 0), Err(MmcqError::NotRowAligned { len: 64, width: 0 }));
+}
+
+#[test]
+fn in_place_returning_palette() {
+    let mut pixels: Vec<u32> = (0..1000u32).map(|i| (i % 200) * 0x010203).collect();
+    let palette = MMCQ::quantize_in_place_returning_palette(&mut pixels, 8);
+    assert_eq!(palette.len(), 8);
+    for rgb in pixels {
+        assert!(palette.iter().any(|&(r, g, b)| ColorNode::pack_rgb(r, g, b) == rgb));
+    }
+}
+
+fn ase_color(name: &str, model: &[u8; 4], values: &[f32]) -> Vec<u8> {
+    // a color entry block of an ASE file
+    let mut data = Vec::new();
+    let units: Vec<u16> = name.encode_utf16().chain(Some(0)).collect();
+    data.extend((units.len() as u16).to_be_bytes());
+    for unit in units {
+        data.extend(unit.to_be_bytes());
+    }
+    data.extend(model);
+    for value in values {
+        data.extend(value.to_be_bytes());
+    }
+    data.extend(0u16.to_be_bytes());
+    let mut block = vec![0, 1];
+    block.extend((data.len() as u32).to_be_bytes());
+    block.extend(data);
+    block
+}
+
+#[test]
+fn ase_palette() {
+    let mut file = b"ASEF".to_vec();
+    file.extend([0, 1, 0, 0]);
+    file.extend(5u32.to_be_bytes());
+    // a group holding two RGB colors and a CMYK one
+    file.extend([0xC0, 0x01, 0, 0, 0, 4, 0, 1, 0, 0]);
+    file.extend(ase_color("Red", b"RGB ", &[1.0, 0.0, 0.0]));
+    file.extend(ase_color("Ink", b"CMYK", &[0.0, 0.0, 0.0, 1.0]));
+    file.extend(ase_color("Teal", b"RGB ", &[0.0, 0.5, 0.5]));
+    file.extend([0xC0, 0x02, 0, 0, 0, 0]);
+    let m = MMCQ::from_ase(&file).unwrap();
+    assert_eq!(m.palette_rgb(), vec![(255, 0, 0), (0, 128, 128)]);
+    assert_eq!(MMCQ::from_ase(b"nope").err(), Some(AseError::MissingSignature));
+    assert!(matches!(MMCQ::from_ase(&file[..30]), Err(AseError::Truncated { .. })));
+}
+
+#[test]
+fn target_total_bytes() {
+    let pixels: Vec<u32> = (0..1000u32).map(|i| i * 16411).collect();
+    let colors = |max_total_bytes| MMCQ::from_pixels_target_total_bytes(&pixels, 1000, max_total_bytes).get_quantized_colors().len();
+    assert!(colors(600) < colors(2000));
+    // 4 bit indices take 500 bytes next to 16 entries, 5 bits would take 625
+    assert_eq!(colors(600), 16);
+    // a single entry needs no index bits
+    assert_eq!(colors(3), 1);
+    assert_eq!(colors(2), 0);
+    assert_eq!(colors(0), 0);
+}
+
+#[test]
+fn neutral_anchor() {
+    // a red and a blue cluster around a smaller gray one
+    let mut pixels = Vec::new();
+    for i in 0..100u32 {
+        pixels.push(0xC02020 + i % 8);
+        pixels.push(0x2020C0 + ((i % 8) << 16));
+        pixels.push(0x808080 + (i % 4) * 0x010101);
+    }
+    let neutral = |m: &MMCQ| m.palette_rgb().iter().any(|&(r, g, b)| r.max(g).max(b) - r.min(g).min(b) <= 8);
+    assert!(!neutral(&MmcqBuilder::new().max_colors(2).build_from_u32(&pixels)));
+    let anchored = MmcqBuilder::new().max_colors(2).neutral_anchor(true).build_from_u32(&pixels);
+    assert!(neutral(&anchored));
+    assert_eq!(anchored.get_quantized_colors().len(), 2);
+}
+
+#[test]
+fn index_grid() {
+    let m = MMCQ::from_centroids(&[(0, 0, 0, 2), (255, 255, 255, 1)]);
+    let grid = m.quantize_to_index_grid(&[0, 0xFFFFFF, 0x101010, 0xF0F0F0, 0, 0], 2).unwrap();
+    assert_eq!(grid, vec![vec![0, 1], vec![0, 1], vec![0, 0]]);
+    assert_eq!(m.quantize_to_index_grid(&[0; 5], 2), Err(MmcqError::NotRowAligned { len: 5, width: 2 }));
+    assert_eq!(m.quantize_to_index_grid(&[0; 4], 0), Err(MmcqError::NotRowAligned { len: 4, width: 0 }));
+    assert_eq!(m.quantize_to_index_grid(&[], 3), Ok(Vec::new()));
+}
+
+fn mse(m: &mut MMCQ, pixels: &Vec<u32>) -> f64 {
+    // mean squared channel error of the remapped pixels
+    let quantized = m.quantize_image(pixels);
+    let channel = |rgb: u32, shift: u32| ((rgb >> shift) & 0xFF) as f64;
+    pixels.iter().zip(&quantized).map(|(&a, &b)| [0, 8, 16].iter().map(|&s| (channel(a, s) - channel(b, s)).powi(2)).sum::<f64>()).sum::<f64>() / pixels.len() as f64
+}
+
+#[test]
+fn seeded_palette_has_lower_error() {
+    // four clusters of different sizes
+    let centers = [(200u32, 30u32), (30, 200), (120, 120), (60, 60)];
+    let mut pixels = Vec::new();
+    for (n, &(rb, g)) in centers.iter().enumerate() {
+        for i in 0..(50 + 40 * n as u32) {
+            let j = i % 7;
+            pixels.push((rb + j) | (g + j) << 8 | (rb + j) << 16);
+        }
+    }
+    let seed: Vec<_> = centers.iter().map(|&(rb, g)| (rb as u8 + 3, g as u8 + 3, rb as u8 + 3)).collect();
+    let mut seeded = MMCQ::from_pixels_seeded(&pixels, 4, &seed);
+    let mut plain = MMCQ::from_pixels_u32_rgba(&pixels, 4);
+    assert!(mse(&mut seeded, &pixels) < mse(&mut plain, &pixels));
+    assert_eq!(seeded.validate(), Ok(()));
+}
+
+#[test]
+fn one_split_event_per_added_color() {
+    let pixels: Vec<u32> = (0..1000u32).map(|i| i * 16411).collect();
+    let (m, events) = MMCQ::from_pixels_u32_rgba_traced(&pixels, 16);
+    assert_eq!(m.get_quantized_colors().len(), 16);
+    assert_eq!(events.len(), m.get_quantized_colors().len() - 1);
+    assert!(events.iter().all(|e| e.lower_count > 0 && e.upper_count > 0));
+}
+
+#[test]
+fn quantize_image_keeps_colors_when_k_max_suffices() {
+    let pixels = vec![0x0000FFu32, 0x00FF00, 0xFF0000, 0x123456, 0x123456];
+    let mut m = MMCQ::from_pixels_u32_rgba(&pixels, 8);
+    assert_eq!(m.quantize_image(&pixels), pixels);
+    assert_eq!(ColorNode::unpack_rgb(0x0000FF), (255, 0, 0));
+    let red = m.get_quantized_colors().iter().find(|c| c.rgb == 0x0000FF).unwrap();
+    assert_eq!((red.red, red.grn, red.blu), (255, 0, 0));
+    assert_eq!(ColorNode::new_rgb(0x563412, 1), ColorNode::new_colors(0x12, 0x34, 0x56, 1));
+}
+
+#[test]
+fn misaligned_rgba_bytes() {
+    assert_eq!(MMCQ::try_from_pixels_u8_rgba(&[1, 2, 3, 4, 5], 4).err(), Some(MmcqError::NotRgbaAligned { len: 5 }));
+    let m = MMCQ::try_from_pixels_u8_rgba(&[1, 2, 3, 255, 1, 2, 3, 255], 4).unwrap();
+    assert_eq!(m.palette_rgb(), vec![(1, 2, 3)]);
+}
+
+#[test]
+#[should_panic(expected = "NotRgbaAligned { len: 5 }")]
+fn misaligned_rgba_bytes_panic() {
+    MMCQ::from_pixels_u8_rgba(&[1, 2, 3, 4, 5], 4);
+}
+
+#[test]
+fn misaligned_byte_slice() {
+    // the pixels start one byte into the buffer, so they are not 4 byte aligned
+    let mut buffer = vec![0u8];
+    for _ in 0..10 {
+        buffer.extend([10, 20, 30, 255]);
+    }
+    for _ in 0..5 {
+        buffer.extend([200, 100, 50, 255]);
+    }
+    let m = MMCQ::from_pixels_u8_rgba(&buffer[1..], 4);
+    assert_eq!(m.palette_with_counts(), vec![((10, 20, 30), 10), ((200, 100, 50), 5)]);
+}
+
+#[test]
+fn every_mapping_path_keeps_alpha() {
+    // half transparent red and opaque green, both in the palette as they are
+    let pixels: Vec<u32> = (0..8).map(|i| if i % 2 == 0 { 0x800000FF } else { 0xFF00FF00 }).collect();
+    let alphas: Vec<u8> = pixels.iter().map(|&px| (px >> 24) as u8).collect();
+    for &mode in &[AlphaMode::Ignore, AlphaMode::Preserve, AlphaMode::Premultiply] {
+        let mut m = MMCQ::from_pixels_u32_rgba_with_alpha(&pixels, 4, mode);
+        let expected = m.quantize_image(&pixels);
+        if mode != AlphaMode::Ignore {
+            assert_eq!(expected[0] >> 24, 0x80, "{:?}", mode);
+        }
+
+        let codec = m.build_codec();
+        let indices = codec.encode(&pixels);
+        assert_eq!(codec.decode_with_alpha(&indices, &alphas), expected, "{:?}", mode);
+        assert_eq!(m.quantize_to_index_grid(&pixels, 4).unwrap().concat(), indices);
+        assert_eq!(m.quantize_with_transparency_mask(&pixels, &alphas, 0).0, indices);
+        assert_eq!(m.quantize_indices_dirty(&[], &pixels, 4).unwrap().0, indices);
+
+        assert_eq!(m.quantize_image_dithered(&pixels, 4, 2), expected, "{:?}", mode);
+        assert_eq!(m.quantize_image_ordered(&pixels, 4, 2, DitherMatrix::Bayer2x2), expected, "{:?}", mode);
+        assert_eq!(m.quantize_image_dithered_masked(&pixels, 4, &[true; 8]).unwrap(), expected, "{:?}", mode);
+        assert_eq!(m.quantize_image_dithered_masked(&pixels, 4, &[false; 8]).unwrap(), expected, "{:?}", mode);
+        assert_eq!(m.quantize_image_adaptive_dither(&pixels, 4).unwrap(), expected, "{:?}", mode);
+        #[cfg(feature = "rayon")]
+        assert_eq!(m.quantize_batch_parallel(&[&pixels])[0], expected, "{:?}", mode);
+
+        let (r, g, b) = ColorNode::unpack_rgb(expected[1]);
+        assert_eq!(m.nearest_index(r, g, b), indices[1] as usize);
+    }
+}
+
+#[test]
+fn palette_tuples_follow_the_node_order() {
+    // blue for 30 pixels, red for 20, green for 10
+    let mut pixels = vec![0xFF0000u32; 30];
+    pixels.extend(vec![0x0000FFu32; 20]);
+    pixels.extend(vec![0x00FF00u32; 10]);
+    let m = MMCQ::from_pixels_u32_rgba(&pixels, 4);
+    let nodes = m.get_quantized_colors();
+    assert!(nodes.windows(2).all(|w| w[0].cnt >= w[1].cnt));
+    assert_eq!(m.palette_rgb(), vec![(0, 0, 255), (255, 0, 0), (0, 255, 0)]);
+    assert_eq!(m.palette_rgb(), nodes.iter().map(|c| (c.red, c.grn, c.blu)).collect::<Vec<_>>());
+    assert_eq!(m.palette_rgba(7), vec![(0, 0, 255, 7), (255, 0, 0, 7), (0, 255, 0, 7)]);
+}
+
+#[test]
+fn kd_tree_matches_linear_scan() {
+    // random palettes, some of them on a coarse grid so that equally distant colors
+    // and duplicates show up, against random pixels
+    let mut seed = 2024u32;
+    let mut next = move || {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        seed >> 8
+    };
+    for round in 0..200 {
+        let len = 1 + next() as usize % 64;
+        let coarse = round % 2 == 0;
+        let palette: Vec<(u8, u8, u8)> = (0..len)
+            .map(|_| {
+                let rgb = next();
+                let c = |shift: u32| if coarse { ((rgb >> shift) & 3) as u8 * 64 } else { (rgb >> shift) as u8 };
+                (c(0), c(8), c(16))
+            })
+            .collect();
+        let m = MMCQ::from_palette_rgb(&palette);
+        for _ in 0..200 {
+            let rgb = next();
+            let rgb = if coarse { rgb & 0xE0E0E0 } else { rgb & 0xFFFFFF };
+            assert_eq!(m.find_closest_color_index(rgb), m.find_closest_color_index_linear(rgb), "{:?} {:06x}", palette, rgb);
+        }
+    }
+}
+
+#[test]
+fn cached_mapping_matches_uncached() {
+    // a gradient with alpha, mapped through the per-color cache and pixel by pixel
+    let pixels: Vec<u32> = (0..64 * 64u32).map(|i| ((i % 64) * 4) | ((i / 64) * 4) << 8 | ((i % 7) * 30) << 16 | (255 - i % 3) << 24).collect();
+    for &mode in &[AlphaMode::Ignore, AlphaMode::Preserve, AlphaMode::Premultiply] {
+        let mut m = MMCQ::from_pixels_u32_rgba_with_alpha(&pixels, 32, mode);
+        let uncached: Vec<u32> = m.quantize_iter(&pixels).collect();
+        assert_eq!(m.quantize_image(&pixels), uncached, "{:?}", mode);
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn parallel_matches_serial() {
+    let pixels: Vec<u32> = (0..20000u32).map(|i| i.wrapping_mul(2654435761)).collect();
+    for &mode in &[AlphaMode::Ignore, AlphaMode::Preserve, AlphaMode::Premultiply] {
+        let mut m = MMCQ::from_pixels_u32_rgba_with_alpha(&pixels, 64, mode);
+        assert_eq!(m.quantize_image_par(&pixels), m.quantize_image(&pixels), "{:?}", mode);
+    }
+}
+
+#[test]
+fn builder_defaults_match_the_constructors() {
+    let pixels: Vec<u32> = (0..5000u32).map(|i| i.wrapping_mul(2654435761) | 0xFF000000).collect();
+    let bytes: Vec<u8> = pixels.iter().flat_map(|px| px.to_le_bytes()).collect();
+    let built = MmcqBuilder::new().max_colors(16).build_from_u32(&pixels);
+    assert_eq!(built.palette_with_counts(), MMCQ::from_pixels_u32_rgba(&pixels, 16).palette_with_counts());
+    let built = MmcqBuilder::new().max_colors(16).build_from_u8_rgba(&bytes);
+    assert_eq!(built.palette_with_counts(), MMCQ::from_pixels_u8_rgba(&bytes, 16).palette_with_counts());
+}
+
+#[test]
+fn last_color_of_the_image_gets_a_box() {
+    // seven distinct colors, the brightest one sorts last in every channel
+    let colors = [0x000000u32, 0x102030, 0x203040, 0x405060, 0x607080, 0x8090A0, 0xFFFFFF];
+    let pixels: Vec<u32> = colors.iter().enumerate().flat_map(|(i, &rgb)| vec![rgb; i + 1]).collect();
+    for k_max in 1..=8 {
+        let m = MMCQ::from_pixels_u32_rgba(&pixels, k_max);
+        assert_eq!(m.validate(), Ok(()), "{}", k_max);
+        assert_eq!(m.palette_with_counts().iter().map(|e| e.1).sum::<usize>(), pixels.len(), "{}", k_max);
+    }
+    let m = MMCQ::from_pixels_u32_rgba(&pixels, 7);
+    assert!(m.palette_rgb().contains(&(255, 255, 255)));
+    assert_eq!(m.palette_with_counts().iter().find(|e| e.0 == (255, 255, 255)).map(|e| e.1), Some(7));
+}
+
+#[test]
+fn box_average_is_the_weighted_mean() {
+    let colors = vec![ColorNode::new_colors(10, 20, 0, 3), ColorNode::new_colors(70, 140, 40, 1)];
+    let mut b = ColorBox::new(0, 2, 0, &colors);
+    assert_eq!(b.count, 4);
+    assert_eq!((b.rmin, b.rmax, b.gmin, b.gmax, b.bmin, b.bmax), (10, 70, 20, 140, 0, 40));
+    let avg = b.get_average_color(&mut colors.clone());
+    assert_eq!((avg.red, avg.grn, avg.blu, avg.cnt), (25, 50, 10, 4));
+
+    let colors = vec![ColorNode::new_colors(0, 0, 0, 2), ColorNode::new_colors(10, 10, 10, 2)];
+    b = ColorBox::new(0, 2, 0, &colors);
+    let avg = b.get_average_color(&mut colors.clone());
+    assert_eq!((avg.red, avg.grn, avg.blu, avg.cnt), (5, 5, 5, 4));
+}
+
+#[test]
+fn rgb_iter_matches_packed_pixels() {
+    let triples: Vec<(u8, u8, u8)> = (0..3000u32).map(|i| (i as u8, (i * 7) as u8, (i * 13 / 5) as u8)).collect();
+    let packed: Vec<u32> = triples.iter().map(|&(r, g, b)| ColorNode::pack_rgb(r, g, b)).collect();
+    let from_iter = MMCQ::from_rgb_iter(triples.iter().copied(), 16);
+    assert_eq!(from_iter.palette_with_counts(), MMCQ::from_pixels_u32_rgba(&packed, 16).palette_with_counts());
+}
+
+#[test]
+fn redmean_prefers_the_perceptually_closer_color() {
+    // from black, a dark green step is 60 away and a dark purple one about 64,
+    // but the eye weights green the most and redmean with it
+    let pixels = vec![ColorNode::pack_rgb(0, 60, 0), ColorNode::pack_rgb(50, 0, 40)];
+    let euclidean = MmcqBuilder::new().max_colors(2).build_from_u32(&pixels);
+    let redmean = MmcqBuilder::new().max_colors(2).metric(DistanceMetric::Redmean).build_from_u32(&pixels);
+    assert_eq!(euclidean.nearest(0, 0, 0), (0, 60, 0));
+    assert_eq!(redmean.nearest(0, 0, 0), (50, 0, 40));
+}
+
+#[test]
+fn lab_quantization_has_lower_lab_error() {
+    // a purple to green gradient with a second ramp across it
+    let mut pixels = Vec::new();
+    for i in 0..256u32 {
+        for j in 0..64u32 {
+            pixels.push(i | (j * 4) << 8 | (255 - i) << 16);
+        }
+    }
+    let error = |space| {
+        let mut m = MmcqBuilder::new().max_colors(16).color_space(space).build_from_u32(&pixels);
+        let out = m.quantize_image(&pixels);
+        let lab = |rgb: u32| {
+            let (red, grn, blu) = ColorNode::unpack_rgb(rgb);
+            color::srgb_to_lab(red, grn, blu)
+        };
+        pixels
+            .iter()
+            .zip(&out)
+            .map(|(&a, &b)| {
+                let (a, b) = (lab(a), lab(b));
+                ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+            })
+            .sum::<f64>()
+            / pixels.len() as f64
+    };
+    assert!(error(ColorSpace::Lab) <= error(ColorSpace::Rgb));
+    assert_eq!(MmcqBuilder::new().color_space(ColorSpace::Lab), MmcqBuilder::new().distance_space(DistanceSpace::Lab));
+}
+
+#[test]
+fn single_color_image_has_no_error() {
+    let pixels = vec![0x336699u32; 100];
+    let m = MMCQ::from_pixels_u32_rgba(&pixels, 8);
+    assert_eq!(m.quantization_error(&pixels), 0.0);
+    assert_eq!(m.mean_quantization_error(&pixels), 0.0);
+    assert_eq!(m.mean_quantization_error(&[]), 0.0);
+
+    // one pixel off by 3 in every channel
+    let mut off = pixels.clone();
+    off[0] = 0x36699C;
+    assert_eq!(m.quantization_error(&off), 27.0);
+    assert_eq!(m.mean_quantization_error(&off), 0.27);
+}
+
+#[test]
+fn indices_rebuild_the_quantized_image() {
+    let pixels: Vec<u32> = (0..4096u32).map(|i| i.wrapping_mul(40503) & 0xFFFFFF).collect();
+    let mut m = MMCQ::from_pixels_u32_rgba(&pixels, 32);
+    let (indices, palette) = m.quantize_to_indices(&pixels);
+    assert_eq!(indices.len(), pixels.len());
+    let rebuilt: Vec<u32> = indices.iter().map(|&i| palette[i as usize]).map(|(r, g, b)| ColorNode::pack_rgb(r, g, b)).collect();
+    assert_eq!(rebuilt, m.quantize_image(&pixels));
+}
+
+#[test]
+fn empty_image() {
+    let mut m = MMCQ::from_pixels_u32_rgba(&[], 16);
+    assert!(m.get_quantized_colors().is_empty());
+    assert!(m.palette_rgb().is_empty());
+    assert!(m.quantize_image(&Vec::new()).is_empty());
+    assert!(MMCQ::from_pixels_u8_rgba(&[], 16).get_quantized_colors().is_empty());
+}
+
+#[test]
+fn single_pixel_image() {
+    let mut m = MMCQ::from_pixels_u32_rgba(&[0x123456], 16);
+    assert_eq!(m.palette_with_counts(), vec![((0x56, 0x34, 0x12), 1)]);
+    assert_eq!(m.validate(), Ok(()));
+    assert_eq!(m.quantize_image(&vec![0x123456, 0xFFFFFF]), vec![0x123456, 0x123456]);
+}
+
+#[test]
+fn zero_k_max_is_one_color() {
+    let pixels: Vec<u32> = (0..1000u32).map(|i| i * 16411).collect();
+    let len = |k_max| MMCQ::from_pixels_u32_rgba(&pixels, k_max).get_quantized_colors().len();
+    assert_eq!((len(0), len(1), len(2)), (1, 1, 2));
+    assert_eq!(MMCQ::from_pixels_u32_rgba(&pixels, 0).palette_rgb(), MMCQ::from_pixels_u32_rgba(&pixels, 1).palette_rgb());
+}
+
+#[test]
+fn u16_channels_are_rounded() {
+    // both share the high byte 0x12, but 0x12FF is closer to 19 than to 18
+    let pixels = vec![[0x1200u16, 0, 0], [0x12FF, 0, 0]];
+    let m = MMCQ::from_pixels_u16_rgb(&pixels, 2);
+    let mut palette = m.palette_rgb();
+    palette.sort();
+    assert_eq!(palette, vec![(18, 0, 0), (19, 0, 0)]);
+    assert_eq!(MMCQ::from_pixels_u16_rgb(&[[0xFFFF, 0x8000, 0]], 1).palette_rgb(), vec![(255, 128, 0)]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn palette_serde_round_trip() {
+    let pixels: Vec<u32> = (0..4096u32).map(|i| i.wrapping_mul(2654435761) & 0xFFFFFF).collect();
+    let mut m = MMCQ::from_pixels_u32_rgba(&pixels, 32);
+    let json = serde_json::to_string(&m.to_palette()).unwrap();
+    let palette: Palette = serde_json::from_str(&json).unwrap();
+    assert_eq!(palette, m.to_palette());
+    let mut reloaded = MMCQ::from_palette(palette);
+    assert_eq!(reloaded.quantize_image(&pixels), m.quantize_image(&pixels));
+}
+
+#[test]
+fn palette_of_one_image_maps_another() {
+    let a: Vec<u32> = (0..4096u32).map(|i| i.wrapping_mul(2654435761) & 0xFFFFFF).collect();
+    let b: Vec<u32> = (0..2048u32).map(|i| i.wrapping_mul(40503) & 0xFFFFFF).collect();
+    let mut m = MMCQ::from_pixels_u32_rgba(&a, 16);
+    let mut reused = MMCQ::from_palette_rgb(&m.palette_rgb());
+    assert_eq!(reused.palette_rgb(), m.palette_rgb());
+    assert!(reused.get_quantized_colors().iter().all(|c| c.cnt == 0));
+    let out = reused.quantize_image(&b);
+    assert_eq!(out, m.quantize_image(&b));
+    assert!(out.iter().all(|&rgb| reused.palette_rgb().contains(&ColorNode::unpack_rgb(rgb))));
+}
+
+fn histogram_buffers() -> Vec<Vec<u32>> {
+    // random with alpha, all the same and all different pixels
+    let random = (0..20000u32).map(|i| i.wrapping_mul(2654435761) >> (i % 9)).collect();
+    let identical = vec![0xFF123456u32; 5000];
+    let distinct = (0..5000u32).map(|i| (i * 3331) | 0x80000000).collect();
+    vec![random, identical, distinct, Vec::new()]
+}
+
+#[test]
+fn histogram_strategies_agree() {
+    for pixels in histogram_buffers() {
+        let sorted = ColorHistogram::new_pixels_sorted(&pixels);
+        for hist in &[ColorHistogram::new_pixels_hashed(&pixels), ColorHistogram::new_pixels_dense(&pixels), ColorHistogram::new_pixels(&pixels)] {
+            assert_eq!(hist.color_array, sorted.color_array);
+            assert_eq!(hist.count_array, sorted.count_array);
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn histogram_benchmark() {
+    // cargo test --release --lib histogram_benchmark -- --ignored --nocapture
+    use std::time::Instant;
+
+    let pixels: Vec<u32> = (0..1u32 << 22).map(|i| i.wrapping_mul(2654435761) >> 6).collect();
+    let time = |name: &str, build: fn(&[u32]) -> ColorHistogram| {
+        let start = Instant::now();
+        let hist = build(&pixels);
+        println!("{}: {} colors in {:?}", name, hist.color_array.len(), start.elapsed());
+    };
+    time("sorted", ColorHistogram::new_pixels_sorted);
+    time("hashed", ColorHistogram::new_pixels_hashed);
+    time("dense", ColorHistogram::new_pixels_dense);
+}
+
+#[test]
+fn single_pass_histogram_matches_two_passes() {
+    // the tabulation as it was before, counting the unique colors up front
+    fn two_pass(pixels: &[u32]) -> (Vec<u32>, Vec<usize>) {
+        let mut sorted: Vec<u32> = pixels.iter().map(|&rgba| rgba & 0xFFFFFF).collect();
+        sorted.sort();
+        let k = sorted.windows(2).filter(|w| w[0] != w[1]).count() + usize::from(!sorted.is_empty());
+        let mut colors = Vec::with_capacity(k);
+        let mut counts: Vec<usize> = Vec::with_capacity(k);
+        for &rgb in &sorted {
+            if colors.last() == Some(&rgb) {
+                *counts.last_mut().unwrap() += 1;
+            } else {
+                colors.push(rgb);
+                counts.push(1);
+            }
+        }
+        (colors, counts)
+    }
+
+    for pixels in histogram_buffers() {
+        let hist = ColorHistogram::new_pixels_sorted(&pixels);
+        assert_eq!((hist.color_array, hist.count_array), two_pass(&pixels));
+    }
+}
+
+#[test]
+fn counts_add_up_to_the_pixel_count() {
+    let pixels: Vec<u32> = (0..32 * 32u32).map(|i| ((i % 32) * 8) | ((i / 32) * 8) << 8).collect();
+    for k_max in &[1, 4, 16, 64] {
+        let counts = MMCQ::from_pixels_u32_rgba(&pixels, *k_max).palette_with_counts();
+        assert_eq!(counts.iter().map(|e| e.1).sum::<usize>(), pixels.len());
+        assert!(counts.windows(2).all(|w| w[0].1 >= w[1].1));
+    }
+}
+
+#[test]
+fn dithered_ramp_alternates_the_palette_colors() {
+    // a black to white ramp over a black and white palette
+    let (width, height) = (64u32, 8u32);
+    let pixels: Vec<u32> = (0..width * height).map(|i| (i % width) * 4 * 0x010101).collect();
+    let m = MMCQ::from_palette_rgb(&[(0, 0, 0), (255, 255, 255)]);
+    let out = m.quantize_image_dithered(&pixels, width, height);
+    assert!(out.iter().all(|&rgb| rgb == 0 || rgb == 0xFFFFFF));
+
+    // the plain mapping steps once per row, the dithered one keeps switching
+    let switches = |row: &[u32]| row.windows(2).filter(|w| w[0] != w[1]).count();
+    let mut plain = pixels.clone();
+    m.quantize_in_place(&mut plain);
+    assert_eq!(switches(&plain[..64]), 1);
+    assert!(out.chunks(64).all(|row| switches(row) > 8));
+    // and the share of white follows the ramp
+    let white = |half: &[u32]| half.iter().filter(|&&rgb| rgb != 0).count();
+    assert!(white(&out[..32]) < white(&out[32..64]));
+}
+
+#[test]
+fn bayer_pattern_on_flat_gray() {
+    // mid gray over black and white turns white where the Bayer index is in the upper half
+    const BAYER4: [[u32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+    let (width, height) = (8u32, 8u32);
+    let pixels = vec![0x808080u32; 64];
+    let m = MMCQ::from_palette_rgb(&[(0, 0, 0), (255, 255, 255)]);
+    let out = m.quantize_image_ordered(&pixels, width, height, DitherMatrix::Bayer4x4);
+    for (i, &rgb) in out.iter().enumerate() {
+        let (x, y) = (i % 8, i / 8);
+        let expected = if BAYER4[y % 4][x % 4] >= 8 { 0xFFFFFF } else { 0 };
+        assert_eq!(rgb, expected, "({}, {})", x, y);
+    }
+    // a single tile comes out as the corner of the larger image
+    let tile = m.quantize_image_ordered(&pixels[..16], 4, 4, DitherMatrix::Bayer4x4);
+    assert_eq!(tile, out.chunks(8).take(4).flat_map(|row| row[..4].to_vec()).collect::<Vec<_>>());
+}
+
+#[test]
+fn chunked_histogram_matches_one_chunk() {
+    let pixels: Vec<u32> = (0..9000u32).map(|i| i.wrapping_mul(2654435761) >> 4).collect();
+    let mut whole = HistogramBuilder::new();
+    whole.add_pixels(&pixels);
+    let mut chunked = HistogramBuilder::new();
+    for chunk in pixels.chunks(3000) {
+        chunked.add_pixels(chunk);
+    }
+    let whole = whole.build(16);
+    assert_eq!(chunked.build(16).palette_with_counts(), whole.palette_with_counts());
+    assert_eq!(whole.palette_with_counts(), MMCQ::from_pixels_u32_rgba(&pixels, 16).palette_with_counts());
+}
+
+#[test]
+fn regions_cover_the_palette() {
+    let pixels: Vec<u32> = (0..5000u32).map(|i| i.wrapping_mul(40503) & 0xFFFFFF).collect();
+    let m = MMCQ::from_pixels_u32_rgba(&pixels, 24);
+    let regions = m.color_regions();
+    assert_eq!(regions.len(), m.get_quantized_colors().len());
+    assert_eq!(regions.iter().map(|r| r.count).sum::<usize>(), pixels.len());
+    for (region, &(r, g, b)) in regions.iter().zip(&m.palette_rgb()) {
+        // the mean of a box lies within it
+        assert!(region.rmin <= r as i32 && r as i32 <= region.rmax);
+        assert!(region.gmin <= g as i32 && g as i32 <= region.gmax);
+        assert!(region.bmin <= b as i32 && b as i32 <= region.bmax);
+    }
+    assert!(MMCQ::from_palette_rgb(&[(1, 2, 3)]).color_regions().is_empty());
+}
+
+#[test]
+fn split_strategies_cut_a_skewed_box_differently() {
+    // most pixels sit at the dark end of a red ramp
+    let mut pixels = vec![ColorNode::pack_rgb(0, 0, 0); 1000];
+    pixels.extend((0..=200u8).map(|r| ColorNode::pack_rgb(r, 0, 0)));
+    let split = |strategy| {
+        let m = MmcqBuilder::new().max_colors(2).split_strategy(strategy).build_from_u32(&pixels);
+        assert_eq!(m.validate(), Ok(()));
+        assert_eq!(m.palette_with_counts().iter().map(|e| e.1).sum::<usize>(), pixels.len());
+        m.color_regions().iter().map(|r| (r.rmin, r.rmax)).collect::<Vec<_>>()
+    };
+    // the population median keeps the dark pile apart, the midpoint halves the range
+    assert_eq!(split(SplitStrategy::PopulationMedian), vec![(0, 0), (1, 200)]);
+    assert_eq!(split(SplitStrategy::Midpoint), vec![(0, 100), (101, 200)]);
+}
+
+#[test]
+fn max_variance_favours_the_denser_mode() {
+    // a dark mode of 10000 pixels and a light one of 500, both spread over the same range
+    let mut seed = 7u32;
+    let mut next = move || {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 16) % 48
+    };
+    let mut pixels = Vec::new();
+    for i in 0..10500 {
+        let base = if i < 10000 { 16 } else { 192 };
+        pixels.push(ColorNode::pack_rgb((base + next()) as u8, (base + next()) as u8, (base + next()) as u8));
+    }
+    // cut at the middle of the ranges, so that the cuts themselves do not follow the pixels
+    let dark_entries = |selection| {
+        let m = MmcqBuilder::new().max_colors(16).split_strategy(SplitStrategy::Midpoint).box_selection(selection).build_from_u32(&pixels);
+        assert_eq!(m.validate(), Ok(()));
+        m.palette_rgb().iter().filter(|c| c.0 < 128).count()
+    };
+    let (min_level, max_variance) = (dark_entries(BoxSelection::MinLevel), dark_entries(BoxSelection::MaxVariance));
+    assert!(max_variance > min_level, "{} {}", max_variance, min_level);
+    assert!(max_variance > 16 - max_variance);
+}
+
+#[test]
+fn palette_colors_are_their_own_nearest() {
+    let pixels: Vec<u32> = (0..5000u32).map(|i| i.wrapping_mul(2654435761) >> 8).collect();
+    for &metric in &[DistanceMetric::Euclidean, DistanceMetric::Redmean, DistanceMetric::WeightedRgb { wr: 2.0, wg: 4.0, wb: 3.0 }] {
+        let m = MmcqBuilder::new().max_colors(32).metric(metric).build_from_u32(&pixels);
+        for (i, &(r, g, b)) in m.palette_rgb().iter().enumerate() {
+            assert_eq!(m.nearest(r, g, b), (r, g, b));
+            assert_eq!(m.nearest_index(r, g, b), i, "{:?}", metric);
+        }
+    }
+}
+
+#[test]
+fn byte_layouts_unpack_to_the_same_color() {
+    // r = 0x11, g = 0x22, b = 0x33, a = 0x44 in every layout
+    let layouts = [
+        (PixelLayout::Rgba, [0x11, 0x22, 0x33, 0x44]),
+        (PixelLayout::Bgra, [0x33, 0x22, 0x11, 0x44]),
+        (PixelLayout::Argb, [0x44, 0x11, 0x22, 0x33]),
+        (PixelLayout::Abgr, [0x44, 0x33, 0x22, 0x11]),
+    ];
+    for &(layout, bytes) in &layouts {
+        let m = MMCQ::from_pixels_u8(&bytes, layout, 4);
+        assert_eq!(m.palette_rgb(), vec![(0x11, 0x22, 0x33)], "{:?}", layout);
+    }
+}
+
+#[test]
+fn luma_ramp_gives_even_levels() {
+    let ramp: Vec<u8> = (0..=255u8).collect();
+    let m = MMCQ::from_luma(&ramp, 4);
+    assert_eq!(m.palette_rgb(), vec![(32, 32, 32), (96, 96, 96), (160, 160, 160), (224, 224, 224)]);
+    // the values halfway between two levels go to the darker one
+    let out = m.quantize_luma(&ramp);
+    let expected: Vec<u8> = ramp.iter().map(|&v| [32, 96, 160, 224][v.saturating_sub(1) as usize / 64]).collect();
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn hex_strings() {
+    assert_eq!(ColorNode::new_colors(255, 0, 0, 1).to_hex(), "#ff0000");
+    assert_eq!(ColorNode::new_colors(0x0a, 0xbc, 0x01, 1).to_string(), "#0abc01");
+    // packed as r | g << 8 | b << 16
+    assert_eq!(ColorNode::new_rgb(0x0000FF, 1).to_hex(), "#ff0000");
+    let m = MMCQ::from_palette_rgb(&[(255, 255, 255), (0, 0, 128)]);
+    assert_eq!(m.palette_hex(), vec!["#ffffff", "#000080"]);
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn rgba_image_round_trip() {
+    let bytes = vec![255, 0, 0, 255, 0, 255, 0, 128, 0, 0, 255, 0, 255, 0, 0, 64];
+    let img = image::RgbaImage::from_raw(2, 2, bytes).unwrap();
+    let m = MMCQ::from_rgba_image(&img, 4);
+    let mut palette = m.palette_rgb();
+    palette.sort();
+    assert_eq!(palette, vec![(0, 0, 255), (0, 255, 0), (255, 0, 0)]);
+    // every color is in the palette, so the image comes back as it was, alpha included
+    let out = m.quantize_rgba_image(&img);
+    assert_eq!(out.dimensions(), (2, 2));
+    assert_eq!(out.into_raw(), img.clone().into_raw());
+
+    let rgb = image::RgbImage::from_raw(2, 2, vec![255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 0, 0]).unwrap();
+    assert_eq!(MMCQ::from_rgb_image(&rgb, 4).palette_with_counts(), m.palette_with_counts());
+}
+
+#[test]
+fn equal_distance_ties_pick_the_lower_packed_color() {
+    // (5, 0, 5) is as far from red as from blue, red packs to the lower value
+    for palette in &[[(10, 0, 0), (0, 0, 10)], [(0, 0, 10), (10, 0, 0)]] {
+        let m = MMCQ::from_palette_rgb(palette);
+        assert_eq!(m.nearest(5, 0, 5), (10, 0, 0));
+        let rgb = ColorNode::pack_rgb(5, 0, 5);
+        assert_eq!(m.find_closest_color_index(rgb), m.find_closest_color_index_linear(rgb));
+    }
+    // the same with a color between two grays
+    for palette in &[[(20, 20, 20), (40, 40, 40)], [(40, 40, 40), (20, 20, 20)]] {
+        assert_eq!(MMCQ::from_palette_rgb(palette).nearest(30, 30, 30), (20, 20, 20));
+    }
+}
+
+#[test]
+fn iterator_matches_quantize_image() {
+    let pixels: Vec<u32> = (0..3000u32).map(|i| i.wrapping_mul(2654435761)).collect();
+    let mut m = MMCQ::from_pixels_u32_rgba(&pixels, 16);
+    let lazy: Vec<u32> = m.quantize_iter(&pixels).collect();
+    assert_eq!(lazy, m.quantize_image(&pixels));
+    assert_eq!(m.quantize_iter(&pixels[10..20]).count(), 10);
+    // an empty palette leaves the pixels as they are
+    assert_eq!(MMCQ::from_palette_rgb(&[]).quantize_iter(&pixels).collect::<Vec<_>>(), pixels);
+}
+
+#[test]
+fn in_place_matches_a_quantized_copy() {
+    let pixels: Vec<u32> = (0..3000u32).map(|i| i.wrapping_mul(2654435761)).collect();
+    for &mode in &[AlphaMode::Ignore, AlphaMode::Preserve, AlphaMode::Premultiply] {
+        let mut m = MMCQ::from_pixels_u32_rgba_with_alpha(&pixels, 16, mode);
+        let mut in_place = pixels.clone();
+        m.quantize_in_place(&mut in_place);
+        assert_eq!(in_place, m.quantize_image(&pixels), "{:?}", mode);
+        if mode != AlphaMode::Ignore {
+            assert!(in_place.iter().zip(&pixels).all(|(a, b)| a >> 24 == b >> 24));
+        }
+    }
+}
+
+#[test]
+fn compact_indices_drop_unused_colors() {
+    // a 16 color palette, the image uses three of its colors
+    let palette: Vec<(u8, u8, u8)> = (0..16u8).map(|i| (i * 16, 255 - i * 16, i * 8)).collect();
+    let m = MMCQ::from_palette_rgb(&palette);
+    let used = [palette[3], palette[9], palette[14]];
+    let pixels: Vec<u32> = (0..30).map(|i| used[i % 3]).map(|(r, g, b)| ColorNode::pack_rgb(r, g, b)).collect();
+    let (indices, compact) = m.quantize_to_indices_compact(&pixels);
+    assert_eq!(compact, used.to_vec());
+    assert!(indices.iter().all(|&i| i < 3));
+    let rebuilt: Vec<u32> = indices.iter().map(|&i| compact[i as usize]).map(|(r, g, b)| ColorNode::pack_rgb(r, g, b)).collect();
+    assert_eq!(rebuilt, pixels);
+}
+
+#[test]
+fn palette_size_is_exactly_k_max() {
+    // 256 distinct colors, one pixel each
+    let pixels: Vec<u32> = (0..256u32).map(|i| ColorNode::pack_rgb(i as u8, (i * 37) as u8, (i * 101) as u8)).collect();
+    for &k_max in &[5, 7, 13, 16] {
+        let m = MMCQ::from_pixels_u32_rgba(&pixels, k_max);
+        assert_eq!(m.get_quantized_colors().len(), k_max as usize);
+        assert_eq!(m.validate(), Ok(()));
+    }
+}
+
+#[test]
+fn outliers_do_not_take_palette_entries() {
+    // a flat gray with a few far off single pixels
+    let mut pixels = vec![0x808080u32; 1000];
+    pixels.extend([0x0000FF, 0x00FF00, 0xFF0000, 0xFFFFFF, 0x000000]);
+    let plain = MMCQ::from_pixels_u32_rgba(&pixels, 8);
+    assert_eq!(plain.palette_with_counts().iter().filter(|e| e.1 == 1).count(), 5);
+
+    let m = MmcqBuilder::new().max_colors(8).min_box_count(10).build_from_u32(&pixels);
+    let counts = m.palette_with_counts();
+    assert!(counts.iter().all(|e| e.1 >= 10), "{:?}", counts);
+    assert_eq!(counts.iter().map(|e| e.1).sum::<usize>(), pixels.len());
+    assert_eq!(m.validate(), Ok(()));
+}
+
+#[test]
+fn five_unique_colors() {
+    // five colors, two of them differing only in alpha
+    let mut pixels: Vec<u32> = (0..100u32).map(|i| [0x000000, 0x0000FF, 0x00FF00, 0xFF0000, 0xFFFFFF][i as usize % 5]).collect();
+    pixels.push(0xFF0000FF);
+    assert_eq!(count_unique_colors(&pixels), 5);
+    assert_eq!(MMCQ::from_pixels_u32_rgba(&pixels, 2).unique_color_count(), 5);
+    assert_eq!(count_unique_colors(&[]), 0);
+}
+
+#[test]
+fn sampling_is_reproducible() {
+    let pixels: Vec<u32> = (0..50000u32).map(|i| i.wrapping_mul(2654435761) >> 8).collect();
+    let sampled = |seed| MMCQ::from_pixels_u32_rgba_sampled(&pixels, 16, 0.1, seed).palette_with_counts();
+    assert_eq!(sampled(42), sampled(42));
+    assert_ne!(sampled(42), sampled(43));
+    let total: usize = sampled(42).iter().map(|e| e.1).sum();
+    assert!(total > 4000 && total < 6000, "{}", total);
+    let full = MMCQ::from_pixels_u32_rgba(&pixels, 16).palette_with_counts();
+    assert_eq!(MMCQ::from_pixels_u32_rgba_sampled(&pixels, 16, 1.0, 42).palette_with_counts(), full);
+
+    for &rate in &[0.0, -0.5, 1.5, f32::NAN, f32::INFINITY] {
+        assert!(matches!(MMCQ::try_from_pixels_u32_rgba_sampled(&pixels, 16, rate, 42), Err(MmcqError::InvalidSampleRate { .. })), "{}", rate);
+    }
+}
+
+#[test]
+fn dominant_color_of_a_mostly_flat_image() {
+    // 90% teal, the rest spread over a few other colors
+    let mut pixels = vec![ColorNode::pack_rgb(0, 128, 128); 900];
+    pixels.extend((0..100u32).map(|i| [0x0000FF, 0x00FF00, 0xFFFFFF, 0x000000][i as usize % 4]));
+    let m = MMCQ::from_pixels_u32_rgba(&pixels, 8);
+    assert_eq!(m.dominant_color(), Some((0, 128, 128)));
+    assert_eq!(m.dominant_colors(2).len(), 2);
+    assert_eq!(m.dominant_colors(2)[0], (0, 128, 128));
+    assert_eq!(m.dominant_colors(100), m.palette_rgb());
+    assert_eq!(MMCQ::from_palette_rgb(&[]).dominant_color(), None);
+}
+
+#[test]
+fn canvas_bytes_in_image_data_order() {
+    // ImageData holds r, g, b, a per pixel whatever the platform byte order
+    let bytes = [200, 100, 50, 255, 200, 100, 50, 0, 10, 20, 30, 255];
+    let m = MMCQ::from_canvas_rgba(&bytes, 4);
+    assert_eq!(m.palette_rgb(), vec![(200, 100, 50), (10, 20, 30)]);
+    assert_eq!(m.palette_flat_rgb(), vec![200, 100, 50, 10, 20, 30]);
+}
+
+#[test]
+fn quantizers_stay_within_k_max() {
+    fn check<Q: Quantizer>(q: &Q, pixels: &[u32], k_max: usize) {
+        let palette: Vec<u32> = q.palette().iter().map(|c| c.rgb).collect();
+        assert!(!palette.is_empty() && palette.len() <= k_max, "{} {}", palette.len(), k_max);
+        let out = q.quantize(pixels);
+        assert_eq!(out.len(), pixels.len());
+        assert!(out.iter().all(|rgb| palette.contains(rgb)));
+    }
+
+    let pixels: Vec<u32> = (0..20000u32).map(|i| i.wrapping_mul(2654435761) >> 8).collect();
+    for &k_max in &[1, 2, 16, 64, 256] {
+        check(&MMCQ::from_pixels_u32_rgba(&pixels, k_max), &pixels, k_max as usize);
+        check(&Octree::from_pixels_u32_rgba(&pixels, k_max), &pixels, k_max as usize);
+    }
+    // few enough colors are kept as they are
+    let few = [0x000000u32, 0x0000FF, 0x00FF00, 0xFF0000];
+    assert_eq!(Octree::from_pixels_u32_rgba(&few, 8).quantize(&few), few.to_vec());
+}
+
+#[test]
+fn level_zero_palette_is_the_average() {
+    let pixels: Vec<u32> = (0..4096u32).map(|i| i.wrapping_mul(2654435761) >> 8).collect();
+    let m = MMCQ::from_pixels_u32_rgba(&pixels, 16);
+    let levels = m.palette_levels();
+    assert_eq!(levels.len(), 16);
+    assert!(levels.iter().all(|&level| level > 0));
+    assert_eq!(m.palette_at_level(*levels.iter().max().unwrap()), m.palette_rgb());
+
+    let coarse = m.palette_at_level(0);
+    assert_eq!(coarse.len(), 1);
+    let mean = |pixels: &[u32], shift: u32| (pixels.iter().map(|&rgb| ((rgb >> shift) & 0xFF) as f64).sum::<f64>() / pixels.len() as f64).round() as u8;
+    assert_eq!(coarse[0], (mean(&pixels, 0), mean(&pixels, 8), mean(&pixels, 16)));
+    assert!(m.palette_at_level(1).len() <= 2);
+
+    // fewer colors than k_max are not split, level 0 is still the image mean
+    let mut pixels = vec![ColorNode::pack_rgb(200, 0, 0); 30];
+    pixels.extend(vec![ColorNode::pack_rgb(0, 0, 100); 10]);
+    let m = MMCQ::from_pixels_u32_rgba(&pixels, 16);
+    assert_eq!(m.palette_at_level(0), vec![(150, 0, 25)]);
+    assert_eq!(m.palette_at_level(1), m.palette_rgb());
+
+    // a seeded palette starts from several boxes, but level 0 is one color all the same
+    let pixels: Vec<u32> = (0..4096u32).map(|i| i.wrapping_mul(2654435761) >> 8).collect();
+    let m = MMCQ::from_pixels_seeded(&pixels, 8, &[(0, 0, 0), (255, 255, 255)]);
+    assert_eq!(m.palette_at_level(0), vec![(mean(&pixels, 0), mean(&pixels, 8), mean(&pixels, 16))]);
+}
+
+#[test]
+fn reduce_merges_the_closest_pair() {
+    // two near-identical reds among far apart colors
+    let mut pixels = vec![ColorNode::pack_rgb(200, 0, 0); 30];
+    pixels.extend(vec![ColorNode::pack_rgb(204, 4, 0); 10]);
+    pixels.extend(vec![ColorNode::pack_rgb(0, 200, 0); 20]);
+    pixels.extend(vec![ColorNode::pack_rgb(0, 0, 200); 25]);
+    let mut m = MMCQ::from_pixels_u32_rgba(&pixels, 4);
+    assert_eq!(m.get_quantized_colors().len(), 4);
+    m.reduce_palette(3);
+    // (200 * 30 + 204 * 10) / 40 = 201, (4 * 10) / 40 = 1
+    assert_eq!(m.palette_with_counts(), vec![((201, 1, 0), 40), ((0, 0, 200), 25), ((0, 200, 0), 20)]);
+}
+
+#[test]
+fn average_of_counts_beyond_u32() {
+    // the channel sums pass u32::MAX: 20 million pixels of 250 and 10 million of 10, the mean is 170
+    let colors = vec![ColorNode::new_colors(250, 250, 250, 20_000_000), ColorNode::new_colors(10, 10, 10, 10_000_000)];
+    let avg = ColorBox::new(0, 2, 0, &colors).get_average_color(&mut colors.clone());
+    assert_eq!((avg.red, avg.grn, avg.blu, avg.cnt), (170, 170, 170, 30_000_000));
+
+    // and so do the counts themselves where usize allows it: 3 and 2 billion pixels, the mean is 154
+    #[cfg(target_pointer_width = "64")]
+    {
+        let colors = vec![ColorNode::new_colors(250, 250, 250, 3_000_000_000), ColorNode::new_colors(10, 10, 10, 2_000_000_000)];
+        let avg = ColorBox::new(0, 2, 0, &colors).get_average_color(&mut colors.clone());
+        assert_eq!((avg.red, avg.grn, avg.blu, avg.cnt), (154, 154, 154, 5_000_000_000));
+    }
+}
+
+#[test]
+fn downscaled_palette_keeps_a_block_sized_accent() {
+    // a 400x300 gray image with a 16x16 red square, downscaled with 4x4 blocks
+    let (width, height) = (400usize, 300usize);
+    let red = ColorNode::pack_rgb(255, 0, 0);
+    let mut pixels = vec![ColorNode::pack_rgb(128, 128, 128); width * height];
+    for y in 100..116 {
+        for x in 200..216 {
+            pixels[y * width + x] = red;
+        }
+    }
+    let m = MMCQ::from_pixels_downscaled(&pixels, width, height, 4, 100);
+    assert_eq!(m.palette_with_counts(), vec![((128, 128, 128), 100 * 75 - 16), ((255, 0, 0), 16)]);
+
+    // a single red pixel is smeared into the mean of its block
+    let mut dot = vec![ColorNode::pack_rgb(128, 128, 128); width * height];
+    dot[100 * width + 200] = red;
+    let m = MMCQ::from_pixels_downscaled(&dot, width, height, 4, 100);
+    assert!(!m.palette_rgb().contains(&(255, 0, 0)));
+}